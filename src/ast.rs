@@ -2,49 +2,124 @@ pub fn pretty_print(module: &Module) {
     println!("AST: {:#?}", module);
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Module {
     pub stmts: Vec<Stmt>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Stmt {
     Expr(Expr),
-    FunDecl{name: String},
-    VarDecl{name: String, value: Expr},
+    /// `import a.b as c` binds module `a/b.njar` to `c`, alias defaults to the last path segment
+    Import{path: String, alias: String},
+    FunDecl{name: String, params: Vec<String>, return_type: String, body: Vec<Stmt>, public: bool},
+    VarDecl{pattern: Pattern, value: Expr, public: bool},
+    /// `target = value`, or `target op= value` when `op` is set
+    Assign{target: Target, op: Option<BinOp>, value: Expr},
+    StructDecl{name: String, fields: Vec<Field>, public: bool},
+    EnumDecl{name: String, variants: Vec<Variant>, public: bool},
+    Return(Option<Expr>),
     If(IfStmt),
+    While{label: Option<String>, test: Expr, body: Vec<Stmt>},
+    For{label: Option<String>, pattern: Pattern, iter: Expr, body: Vec<Stmt>},
+    /// Leaves the innermost loop, or the one with the label
+    Break(Option<String>),
+    /// Starts the next iteration of the innermost loop, or of the one with the label
+    Continue(Option<String>),
+    Throw(Expr),
+    Try(TryStmt),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub public: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+/// Binding target of `let` and `for`
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Ident(String),
+    Wildcard,
+    Tuple(Vec<Pattern>),
+}
+
+/// Left side of an assignment
+#[derive(Debug, Clone)]
+pub enum Target {
+    Name(String),
+    Attr{base: Expr, name: String},
+    Index{base: Expr, index: Expr},
+}
+
+/// At least one of `catch` and `finally` is present
+#[derive(Debug, Clone)]
+pub struct TryStmt {
+    pub body: Vec<Stmt>,
+    pub catch: Option<Catch>,
+    pub finally: Option<Vec<Stmt>>,
+}
+
+/// `catch e { }` binds the exception to `e`, `catch { }` drops it
+#[derive(Debug, Clone)]
+pub struct Catch {
+    pub name: Option<String>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone)]
 pub struct IfStmt {
     pub if_test: Expr,
     pub if_body: Vec<Stmt>,
     pub else_body: Option<Vec<Stmt>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Expr {
     VarCall{name: String},
-    FunCall{name: String, args: Vec<Expr>},
+    FunCall{name: String, args: Vec<Expr>, kwargs: Vec<(String, Expr)>},
+    StructInit{name: String, fields: Vec<(String, Expr)>},
+    None,
     Integer(i64),
+    Float(f64),
     String(String),
+    FString(Vec<FStringPart>),
+    Tuple(Vec<Expr>),
+    List(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
     Unary{ op: UnaryOp, expr: Box<Expr>},
     Binary { lhs: Box<Expr>, op: BinOp, rhs: Box<Expr> },
+    Index { base: Box<Expr>, index: Box<Expr> },
+    /// `expr?` unwraps an `Ok` result and returns an `Err` one from the function
+    Try(Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub enum FStringPart {
+    Literal(String),
+    Field{expr: Expr, spec: Option<String>},
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum UnaryOp {
     Negative,
     BitNot,
     Not,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BinOp {
     Exp,
     Mul,
     MatMul,
     Div,
+    FloorDiv,
     Rem,
     Add,
     Sub,
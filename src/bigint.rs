@@ -0,0 +1,463 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Arbitrary-precision integer, used when `i64` arithmetic overflows.
+/// Magnitude is stored as little-endian base 2^32 limbs without trailing zeros,
+/// so zero is an empty vec and is never negative
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    fn from_limbs(negative: bool, mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        let negative = negative && !limbs.is_empty();
+        BigInt { negative, limbs }
+    }
+
+    pub fn from_u64(value: u64) -> Self {
+        BigInt::from_limbs(false, vec![value as u32, (value >> 32) as u32])
+    }
+
+    /// Digits with an optional sign, `None` when a digit is not valid in `radix`
+    pub fn parse(text: &str, radix: u32) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        if digits.is_empty() {
+            return None;
+        }
+        let base = BigInt::from(radix as i64);
+        let mut value = BigInt::from(0);
+        for c in digits.chars() {
+            let digit = BigInt::from(c.to_digit(radix)? as i64);
+            value = &(&value * &base) + &digit;
+        }
+        Some(if negative { -&value } else { value })
+    }
+
+    /// Integral part of a finite float, `None` for infinities and NaN
+    pub fn from_f64(value: f64) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        let value = value.trunc();
+        if value.abs() < 9.2e18 {
+            return Some(BigInt::from(value as i64));
+        }
+        // At this magnitude the float is an integral mantissa shifted left by `exponent`
+        let bits = value.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64 - 1075;
+        let mantissa = BigInt::from_u64((bits & ((1 << 52) - 1)) | (1 << 52));
+        let shift = (0..exponent).fold(BigInt::from(1), |acc, _| &acc * &BigInt::from(2));
+        let magnitude = &mantissa * &shift;
+        Some(if value < 0.0 { -&magnitude } else { magnitude })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.limbs.len() > 2 {
+            return None;
+        }
+        let magnitude = self.limbs.iter().rev().fold(0i128, |acc, &limb| (acc << 32) | limb as i128);
+        let value = if self.negative { -magnitude } else { magnitude };
+        i64::try_from(value).ok()
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        if self.negative || self.limbs.len() > 2 {
+            return None;
+        }
+        Some(self.limbs.iter().rev().fold(0u64, |acc, &limb| (acc << 32) | limb as u64))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self.limbs.iter().rev().fold(0.0, |acc, &limb| acc * 4294967296.0 + limb as f64);
+        if self.negative { -magnitude } else { magnitude }
+    }
+
+    /// Truncated division, the remainder has the sign of the dividend.
+    /// Returns `None` on division by zero
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (q, r) = divrem_mag(&self.limbs, &other.limbs);
+        Some((
+            BigInt::from_limbs(self.negative != other.negative, q),
+            BigInt::from_limbs(self.negative, r),
+        ))
+    }
+
+    /// Multiplied by 2^`bits`
+    pub fn shl(&self, bits: u32) -> BigInt {
+        let mut limbs = vec![0u32; (bits / 32) as usize];
+        limbs.extend(shl_bits(&self.limbs, bits % 32));
+        BigInt::from_limbs(self.negative, limbs)
+    }
+
+    /// Divided by 2^`bits` rounding towards negative infinity, as shifting
+    /// the two's complement would
+    pub fn shr(&self, bits: u32) -> BigInt {
+        if self.negative {
+            // -x >> n is -((x - 1) >> n) - 1
+            let one = BigInt::from(1);
+            let shifted = (&-self - &one).shr(bits);
+            return &-&shifted - &one;
+        }
+        match self.limbs.get((bits / 32) as usize..) {
+            Some(limbs) => BigInt::from_limbs(false, shr_bits(limbs, bits % 32)),
+            None => BigInt::from(0),
+        }
+    }
+
+    /// Applies `op` limb by limb to two's complement forms, in which negative
+    /// numbers have infinitely many leading ones
+    pub fn bitwise(&self, other: &BigInt, op: fn(u32, u32) -> u32) -> BigInt {
+        let len = self.limbs.len().max(other.limbs.len()) + 1;
+        let limbs: Vec<u32> = self.twos_complement(len).into_iter()
+            .zip(other.twos_complement(len))
+            .map(|(a, b)| op(a, b))
+            .collect();
+        if limbs[len - 1] >> 31 == 0 {
+            return BigInt::from_limbs(false, limbs);
+        }
+        let inverted: Vec<u32> = limbs.iter().map(|limb| !limb).collect();
+        BigInt::from_limbs(true, add_mag(&inverted, &[1]))
+    }
+
+    /// Lowest `len` limbs of the two's complement, -x being !(x - 1)
+    fn twos_complement(&self, len: usize) -> Vec<u32> {
+        let mut limbs = match self.negative {
+            true => sub_mag(&self.limbs, &[1]).iter().map(|limb| !limb).collect(),
+            false => self.limbs.clone(),
+        };
+        limbs.resize(len, if self.negative { u32::MAX } else { 0 });
+        limbs
+    }
+
+    /// Floored division, the remainder has the sign of the divisor.
+    /// Returns `None` on division by zero
+    pub fn div_rem_floor(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        let (q, r) = self.div_rem(other)?;
+        if !r.is_zero() && r.negative != other.negative {
+            Some((&q - &BigInt::from(1), &r + other))
+        } else {
+            Some((q, r))
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> Self {
+        let magnitude = value.unsigned_abs();
+        BigInt::from_limbs(value < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.limbs, &other.limbs),
+            (true, true) => cmp_mag(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_limbs(!self.negative, self.limbs.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_limbs(self.negative, add_mag(&self.limbs, &other.limbs));
+        }
+        match cmp_mag(&self.limbs, &other.limbs) {
+            Ordering::Less => BigInt::from_limbs(other.negative, sub_mag(&other.limbs, &self.limbs)),
+            _ => BigInt::from_limbs(self.negative, sub_mag(&self.limbs, &other.limbs)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_limbs(self.negative != other.negative, mul_mag(&self.limbs, &other.limbs))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const CHUNK: u32 = 1_000_000_000;
+
+        let mut chunks = vec![];
+        let mut limbs = self.limbs.clone();
+        while !limbs.is_empty() {
+            let (q, r) = divrem_mag(&limbs, &[CHUNK]);
+            chunks.push(r.first().copied().unwrap_or(0));
+            limbs = q;
+        }
+
+        let mut s = String::new();
+        if self.negative {
+            s.push('-');
+        }
+        match chunks.split_last() {
+            Some((first, rest)) => {
+                s.push_str(&first.to_string());
+                for chunk in rest.iter().rev() {
+                    s.push_str(&format!("{:09}", chunk));
+                }
+            }
+            None => s.push('0'),
+        }
+        f.write_str(&s)
+    }
+}
+
+
+fn trim(mut limbs: Vec<u32>) -> Vec<u32> {
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+    limbs
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let sum = limb as u64 + short.get(i).copied().unwrap_or(0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    result.push(carry as u32);
+    trim(result)
+}
+
+/// Requires `a >= b`
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let diff = limb as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        result.push(diff as u32);
+        borrow = (diff < 0) as i64;
+    }
+    trim(result)
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let cur = result[i + j] as u64 + x as u64 * y as u64 + carry;
+            result[i + j] = cur as u32;
+            carry = cur >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    trim(result)
+}
+
+fn shl_bits(limbs: &[u32], shift: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(limbs.len() + 1);
+    let mut carry = 0u32;
+    for &limb in limbs {
+        result.push((limb << shift) | carry);
+        carry = if shift == 0 { 0 } else { limb >> (32 - shift) };
+    }
+    result.push(carry);
+    result
+}
+
+fn shr_bits(limbs: &[u32], shift: u32) -> Vec<u32> {
+    let mut result = vec![0u32; limbs.len()];
+    for i in 0..limbs.len() {
+        let high = if shift == 0 { 0 } else { limbs.get(i + 1).map_or(0, |&l| l << (32 - shift)) };
+        result[i] = (limbs[i] >> shift) | high;
+    }
+    trim(result)
+}
+
+/// Schoolbook long division on magnitudes (Knuth, TAOCP vol. 2, algorithm D)
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(a, b) == Ordering::Less {
+        return (vec![], a.to_vec());
+    }
+
+    if let [divisor] = b {
+        let divisor = *divisor as u64;
+        let mut quotient = vec![0u32; a.len()];
+        let mut rem = 0u64;
+        for i in (0..a.len()).rev() {
+            let cur = (rem << 32) | a[i] as u64;
+            quotient[i] = (cur / divisor) as u32;
+            rem = cur % divisor;
+        }
+        return (trim(quotient), trim(vec![rem as u32]));
+    }
+
+    // Normalize so the top limb of the divisor has its high bit set
+    let shift = b.last().unwrap().leading_zeros();
+    let v = trim(shl_bits(b, shift));
+    let mut u = shl_bits(a, shift);
+    let n = v.len();
+    let m = u.len() - n - 1;
+    let mut quotient = vec![0u32; m + 1];
+
+    for j in (0..=m).rev() {
+        let num = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+        let mut qhat = num / v[n - 1] as u64;
+        let mut rhat = num % v[n - 1] as u64;
+        while qhat >> 32 != 0 || qhat * v[n - 2] as u64 > ((rhat << 32) | u[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += v[n - 1] as u64;
+            if rhat >> 32 != 0 {
+                break;
+            }
+        }
+
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = qhat * v[i] as u64 + carry;
+            carry = product >> 32;
+            let diff = u[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+            u[i + j] = diff as u32;
+            borrow = (diff < 0) as i64;
+        }
+        let diff = u[j + n] as i64 - borrow - carry as i64;
+        u[j + n] = diff as u32;
+
+        if diff < 0 {
+            // qhat was one too large, add the divisor back
+            qhat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                u[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = qhat as u32;
+    }
+
+    (trim(quotient), shr_bits(&u[..n], shift))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn factorial(n: i64) -> BigInt {
+        (1..=n).fold(BigInt::from(1), |acc, x| &acc * &BigInt::from(x))
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(BigInt::from(0).to_string(), "0");
+        assert_eq!(BigInt::from(i64::MIN).to_string(), "-9223372036854775808");
+        assert_eq!(factorial(30).to_string(), "265252859812191058636308480000000");
+    }
+
+    #[test]
+    fn arithmetic() {
+        let max = BigInt::from(i64::MAX);
+        let one = BigInt::from(1);
+        assert_eq!((&(&max + &one) - &one).to_i64(), Some(i64::MAX));
+        assert_eq!((&max + &one).to_i64(), None);
+        assert_eq!((&BigInt::from(-5) + &BigInt::from(3)).to_i64(), Some(-2));
+
+        let (q, r) = factorial(30).div_rem(&factorial(28)).unwrap();
+        assert_eq!(q.to_i64(), Some(29 * 30));
+        assert!(r.is_zero());
+
+        let (q, r) = (&factorial(25) + &BigInt::from(7)).div_rem(&BigInt::from(-1_000_000_000_039)).unwrap();
+        assert_eq!(q.to_i64(), Some(-15511210042726));
+        assert_eq!(r.to_i64(), Some(48792333693));
+        assert!(one.div_rem(&BigInt::from(0)).is_none());
+
+        let (q, r) = BigInt::from(-7).div_rem_floor(&BigInt::from(2)).unwrap();
+        assert_eq!((q.to_i64(), r.to_i64()), (Some(-4), Some(1)));
+        let (q, r) = BigInt::from(7).div_rem_floor(&BigInt::from(-2)).unwrap();
+        assert_eq!((q.to_i64(), r.to_i64()), (Some(-4), Some(-1)));
+    }
+
+    #[test]
+    fn shifts_and_bitwise() {
+        let big = &factorial(30) + &BigInt::from(1);
+        assert_eq!(big.shl(70).shr(70), big);
+        assert_eq!(BigInt::from(3).shl(64).to_string(), "55340232221128654848");
+        assert_eq!(BigInt::from(-5).shr(1).to_i64(), Some(-3));
+        assert_eq!((-&big).shr(200).to_i64(), Some(-1));
+        assert!(big.shr(200).is_zero());
+
+        let and = |a: i64, b: i64| BigInt::from(a).bitwise(&BigInt::from(b), |x, y| x & y).to_i64();
+        let or = |a: i64, b: i64| BigInt::from(a).bitwise(&BigInt::from(b), |x, y| x | y).to_i64();
+        let xor = |a: i64, b: i64| BigInt::from(a).bitwise(&BigInt::from(b), |x, y| x ^ y).to_i64();
+        for (a, b) in [(12, 10), (-12, 10), (12, -10), (-12, -10), (i64::MIN, -1), (0, -1)] {
+            assert_eq!(and(a, b), Some(a & b));
+            assert_eq!(or(a, b), Some(a | b));
+            assert_eq!(xor(a, b), Some(a ^ b));
+        }
+        assert_eq!(big.bitwise(&BigInt::from(-1), |x, y| x & y), big);
+        assert_eq!(big.bitwise(&big, |x, y| x ^ y), BigInt::from(0));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(BigInt::parse("-265252859812191058636308480000000", 10), Some(-&factorial(30)));
+        assert_eq!(BigInt::parse("ff", 16).and_then(|v| v.to_i64()), Some(255));
+        assert_eq!(BigInt::parse("12a", 10), None);
+        assert_eq!(BigInt::parse("-", 10), None);
+        assert_eq!(BigInt::from_f64(-2.5e19).unwrap().to_string(), "-25000000000000000000");
+        assert_eq!(BigInt::from_f64(-7.9).and_then(|v| v.to_i64()), Some(-7));
+        assert_eq!(BigInt::from_f64(f64::NAN), None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs;
+use std::rc::Rc;
+
+use crate::convert::{type_error, FromValue, IntoValue};
+use crate::error::RuntimeError;
+use crate::native::{NativeModule, Natives, Signature};
+use crate::value::{FileHandle, Value};
+use crate::vm::VM;
+
+/// `fs` module, paths are relative to the working directory
+pub fn register(natives: &mut Natives) {
+    let module = NativeModule::new("fs")
+        .function("read_file", 1, read_file)
+        .function("write_file", 2, write_file)
+        .function("append_file", 2, append_file)
+        .function("exists", 1, exists)
+        .function("list_dir", 1, list_dir)
+        .function("remove", 1, remove)
+        .function("open", Signature::fixed(1).keywords(&["mode"]), open)
+        .function("read_line", 1, read_line)
+        .function("write", 2, write)
+        .function("close", 1, close);
+    natives.register_module(module);
+}
+
+fn file_arg(value: &Value) -> Result<&Rc<FileHandle>, RuntimeError> {
+    match value {
+        Value::File(file) => Ok(file),
+        value => Err(type_error("file", value)),
+    }
+}
+
+fn read_file(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::from_value(&args[0])?;
+    let text = fs::read_to_string(&path).map_err(|err| RuntimeError::io_at(&path, err))?;
+    Ok(text.into_value())
+}
+
+/// Creates the file or replaces its content
+fn write_file(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::from_value(&args[0])?;
+    let text = String::from_value(&args[1])?;
+    fs::write(&path, text).map_err(|err| RuntimeError::io_at(&path, err))?;
+    Ok(Value::None)
+}
+
+fn append_file(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::from_value(&args[0])?;
+    let file = FileHandle::open(&path, "a")?;
+    file.write(&String::from_value(&args[1])?)?;
+    Ok(Value::None)
+}
+
+fn exists(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::from_value(&args[0])?;
+    Ok(fs::exists(&path).map_err(|err| RuntimeError::io_at(&path, err))?.into_value())
+}
+
+/// Entry names in sorted order, without the directory
+fn list_dir(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::from_value(&args[0])?;
+    let entries = fs::read_dir(&path).map_err(|err| RuntimeError::io_at(&path, err))?;
+    let mut names = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|err| RuntimeError::io_at(&path, err))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(names.into_value())
+}
+
+/// Removes a file or an empty directory
+fn remove(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::from_value(&args[0])?;
+    let result = match fs::metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(&path),
+        _ => fs::remove_file(&path),
+    };
+    result.map_err(|err| RuntimeError::io_at(&path, err))?;
+    Ok(Value::None)
+}
+
+/// `mode` is `r` by default, see `FileHandle::open`
+fn open(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::from_value(&args[0])?;
+    let mode = Option::<String>::from_value(&args[1])?.unwrap_or_else(|| "r".to_string());
+    Ok(Value::File(Rc::new(FileHandle::open(&path, &mode)?)))
+}
+
+/// Next line without its terminator, none at end of file
+fn read_line(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(file_arg(&args[0])?.read_line()?.into_value())
+}
+
+fn write(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    file_arg(&args[0])?.write(&String::from_value(&args[1])?)?;
+    Ok(Value::None)
+}
+
+fn close(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    file_arg(&args[0])?.close();
+    Ok(Value::None)
+}
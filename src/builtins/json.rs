@@ -0,0 +1,432 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::Chars;
+
+use crate::bigint::BigInt;
+use crate::convert::{FromValue, IntoValue};
+use crate::error::{ErrorKind, RuntimeError};
+use crate::native::{NativeModule, Natives, Signature};
+use crate::value::Value;
+use crate::vm::VM;
+
+/// Deeper documents are rejected rather than overflowing the stack,
+/// this also stops `stringify` on lists which contain themselves
+const MAX_DEPTH: usize = 512;
+/// Largest number of spaces per level `stringify` accepts
+const MAX_INDENT: usize = 32;
+
+/// `json` module, `true` and `false` map to 1 and 0 since scripts have no bool type
+pub fn register(natives: &mut Natives) {
+    let module = NativeModule::new("json")
+        .function("parse", 1, parse)
+        .function("stringify", Signature::fixed(1).optional(1), stringify);
+    natives.register_module(module);
+}
+
+fn parse(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let text = String::from_value(&args[0])?;
+    let mut parser = Parser { chars: text.chars().peekable(), line: 1, column: 1, depth: 0 };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.chars.peek().is_some() {
+        return Err(parser.error("unexpected data after the value"));
+    }
+    Ok(value)
+}
+
+/// Compact output unless `indent` gives the number of spaces per level, at most `MAX_INDENT`
+fn stringify(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let indent = match &args[1] {
+        Value::None => None,
+        value => {
+            let indent = i64::from_value(value)?;
+            if !(0..=MAX_INDENT as i64).contains(&indent) {
+                let message = format!("indent must be in 0..={}, got {}", MAX_INDENT, indent);
+                return Err(RuntimeError::new(ErrorKind::Value, message));
+            }
+            Some(indent as usize)
+        }
+    };
+    let mut out = String::new();
+    write_value(&mut out, &args[0], indent, 0)?;
+    Ok(out.into_value())
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> RuntimeError {
+        let message = format!("invalid json: {} at line {}, column {}", message, self.line, self.column);
+        RuntimeError::new(ErrorKind::Value, message)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), RuntimeError> {
+        match self.chars.peek() {
+            Some(&c) if c == expected => {
+                self.next();
+                Ok(())
+            }
+            Some(&c) => Err(self.error(&format!("expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(&format!("expected '{}', found end of input", expected))),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, RuntimeError> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => self.nested(Parser::parse_object),
+            Some('[') => self.nested(Parser::parse_array),
+            Some('"') => Ok(self.parse_string()?.into_value()),
+            Some('-' | '0'..='9') => self.parse_number(),
+            Some('t') => self.parse_literal("true", Value::Integer(1)),
+            Some('f') => self.parse_literal("false", Value::Integer(0)),
+            Some('n') => self.parse_literal("null", Value::None),
+            Some(&c) => Err(self.error(&format!("unexpected '{}'", c))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, RuntimeError>) -> Result<Value, RuntimeError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_literal(&mut self, word: &str, value: Value) -> Result<Value, RuntimeError> {
+        for expected in word.chars() {
+            if self.chars.peek() != Some(&expected) {
+                return Err(self.error(&format!("invalid literal, expected {}", word)));
+            }
+            self.next();
+        }
+        Ok(value)
+    }
+
+    fn parse_object(&mut self) -> Result<Value, RuntimeError> {
+        self.expect('{')?;
+        let mut entries = BTreeMap::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.next();
+            return Ok(Value::Map(Rc::new(RefCell::new(entries))));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'"') {
+                return Err(self.error("expected string key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.parse_value()?;
+            entries.insert(key, value);
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some(',') => self.next(),
+                Some('}') => {
+                    self.next();
+                    break;
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            };
+        }
+        Ok(Value::Map(Rc::new(RefCell::new(entries))))
+    }
+
+    fn parse_array(&mut self) -> Result<Value, RuntimeError> {
+        self.expect('[')?;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.next();
+            return Ok(Value::new_list(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some(',') => self.next(),
+                Some(']') => {
+                    self.next();
+                    break;
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            };
+        }
+        Ok(Value::new_list(items))
+    }
+
+    fn parse_string(&mut self) -> Result<String, RuntimeError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.parse_unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    s.push(c);
+                }
+                Some(c) if (c as u32) < 0x20 => return Err(self.error("control character in string")),
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// `\uXXXX`, characters outside the basic plane come as a surrogate pair
+    fn parse_unicode_escape(&mut self) -> Result<char, RuntimeError> {
+        let high = self.parse_hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("invalid unicode escape"));
+        }
+        if self.next() != Some('\\') || self.next() != Some('u') {
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+        let low = self.parse_hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, RuntimeError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next().and_then(|c| c.to_digit(16)).ok_or_else(|| self.error("invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    /// Integers without fraction or exponent stay integers, big ones included
+    fn parse_number(&mut self) -> Result<Value, RuntimeError> {
+        let mut text = String::new();
+        let mut is_float = false;
+        if self.chars.peek() == Some(&'-') {
+            text.push('-');
+            self.next();
+        }
+        match self.chars.peek() {
+            Some('0') => text.push(self.next().unwrap()),
+            Some('1'..='9') => self.digits(&mut text),
+            _ => return Err(self.error("expected digit")),
+        }
+        if self.chars.peek() == Some(&'.') {
+            is_float = true;
+            text.push(self.next().unwrap());
+            if !matches!(self.chars.peek(), Some('0'..='9')) {
+                return Err(self.error("expected digit after '.'"));
+            }
+            self.digits(&mut text);
+        }
+        if matches!(self.chars.peek(), Some('e' | 'E')) {
+            is_float = true;
+            text.push(self.next().unwrap());
+            if let Some(&sign @ ('+' | '-')) = self.chars.peek() {
+                text.push(sign);
+                self.next();
+            }
+            if !matches!(self.chars.peek(), Some('0'..='9')) {
+                return Err(self.error("expected digit in exponent"));
+            }
+            self.digits(&mut text);
+        }
+        if is_float {
+            return text.parse().map(Value::Float).map_err(|_| self.error("invalid number"));
+        }
+        BigInt::parse(&text, 10).map(Value::from_bigint).ok_or_else(|| self.error("invalid number"))
+    }
+
+    fn digits(&mut self, text: &mut String) {
+        while let Some(&c @ '0'..='9') = self.chars.peek() {
+            text.push(c);
+            self.next();
+        }
+    }
+}
+
+fn write_value(out: &mut String, value: &Value, indent: Option<usize>, depth: usize) -> Result<(), RuntimeError> {
+    if depth == MAX_DEPTH {
+        return Err(RuntimeError::new(ErrorKind::Value, "json nesting too deep, the value may contain itself"));
+    }
+    match value {
+        Value::None => out.push_str("null"),
+        Value::Integer(x) => write!(out, "{}", x).unwrap(),
+        Value::BigInt(x) => write!(out, "{}", x).unwrap(),
+        Value::Float(f) if f.is_finite() => write!(out, "{:?}", f).unwrap(),
+        Value::Float(f) => {
+            return Err(RuntimeError::new(ErrorKind::Value, format!("{:?} is not allowed in json", f)));
+        }
+        Value::String(s) => write_string(out, s),
+        Value::Tuple(items) => write_array(out, items, indent, depth)?,
+        Value::List(items) => write_array(out, &items.borrow(), indent, depth)?,
+        Value::Map(entries) => {
+            let entries = entries.borrow();
+            write_object(out, entries.iter().map(|(k, v)| (k.as_str(), v)), indent, depth)?;
+        }
+        Value::Struct(instance) => {
+            let fields = instance.fields.borrow();
+            let entries = instance.ty.fields.iter().map(|field| field.name.as_str()).zip(fields.iter());
+            write_object(out, entries, indent, depth)?;
+        }
+        value => {
+            return Err(RuntimeError::new(ErrorKind::Type, format!("'{}' cannot be converted to json", value.type_name())));
+        }
+    }
+    Ok(())
+}
+
+/// Line break and indentation before an item, nothing in compact output
+fn newline(out: &mut String, indent: Option<usize>, depth: usize) {
+    if let Some(indent) = indent {
+        out.push('\n');
+        out.push_str(&" ".repeat(indent * depth));
+    }
+}
+
+fn write_array(out: &mut String, items: &[Value], indent: Option<usize>, depth: usize) -> Result<(), RuntimeError> {
+    out.push('[');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        newline(out, indent, depth + 1);
+        write_value(out, item, indent, depth + 1)?;
+    }
+    if !items.is_empty() {
+        newline(out, indent, depth);
+    }
+    out.push(']');
+    Ok(())
+}
+
+fn write_object<'a>(
+    out: &mut String, entries: impl Iterator<Item = (&'a str, &'a Value)>, indent: Option<usize>, depth: usize,
+) -> Result<(), RuntimeError> {
+    out.push('{');
+    let mut empty = true;
+    for (key, value) in entries {
+        if !empty {
+            out.push(',');
+        }
+        empty = false;
+        newline(out, indent, depth + 1);
+        write_string(out, key);
+        out.push_str(if indent.is_some() { ": " } else { ":" });
+        write_value(out, value, indent, depth + 1)?;
+    }
+    if !empty {
+        newline(out, indent, depth);
+    }
+    out.push('}');
+    Ok(())
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(text: &str) -> Result<Value, RuntimeError> {
+        parse(&mut VM::new(), &[text.into_value()])
+    }
+
+    fn same(a: &Value, b: &Value) -> bool {
+        a.clone().__et__(b.clone()).unwrap().as_bool()
+    }
+
+    fn stringify_value(value: &Value, indent: Option<usize>) -> String {
+        stringify(&mut VM::new(), &[value.clone(), indent.into_value()]).unwrap().__str__()
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"a": [1, -2.5, 1e3, true, null], "b": {"c": "q\"\\\né😀"}, "d": 123456789012345678901234567890}"#;
+        let value = parse_str(text).unwrap();
+        let compact = stringify_value(&value, None);
+        assert_eq!(compact, "{\"a\":[1,-2.5,1000.0,1,null],\"b\":{\"c\":\"q\\\"\\\\\\né😀\"},\"d\":123456789012345678901234567890}");
+        assert!(same(&parse_str(&compact).unwrap(), &value));
+
+        let pretty = stringify_value(&value, Some(2));
+        assert!(pretty.starts_with("{\n  \"a\": [\n    1,\n"));
+        assert!(same(&parse_str(&pretty).unwrap(), &value));
+        assert_eq!(stringify_value(&parse_str("[[], {}]").unwrap(), Some(2)), "[\n  [],\n  {}\n]");
+    }
+
+    #[test]
+    fn indent_range() {
+        let value = parse_str("[1]").unwrap();
+        assert_eq!(stringify_value(&value, Some(MAX_INDENT)), format!("[\n{}1\n]", " ".repeat(MAX_INDENT)));
+        for indent in [Value::Integer(MAX_INDENT as i64 + 1), Value::Integer(10_i64.pow(12)), Value::Integer(-1)] {
+            let err = stringify(&mut VM::new(), &[value.clone(), indent]).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::Value);
+        }
+    }
+
+    #[test]
+    fn error_position() {
+        let err = parse_str("{\n  \"a\": [1,\n  2 3]\n}").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Value);
+        assert!(err.message.ends_with("expected ',' or ']' at line 3, column 5"), "{}", err.message);
+        assert!(parse_str("[1] x").unwrap_err().message.contains("line 1, column 5"));
+        assert!(parse_str("\"abc").is_err());
+        assert!(parse_str("01").is_err());
+        assert!(parse_str(&"[".repeat(MAX_DEPTH + 1)).unwrap_err().message.contains("too deep"));
+    }
+}
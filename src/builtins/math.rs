@@ -0,0 +1,205 @@
+use std::f64::consts;
+
+use crate::convert::{type_error, FromValue};
+use crate::error::{ErrorKind, RuntimeError};
+use crate::native::{NativeModule, Natives, Signature};
+use crate::value::Value;
+use crate::vm::VM;
+
+/// `math` module, functions take ints and floats alike
+pub fn register(natives: &mut Natives) {
+    let module = NativeModule::new("math")
+        .constant("pi", Value::Float(consts::PI))
+        .constant("e", Value::Float(consts::E))
+        .constant("inf", Value::Float(f64::INFINITY))
+        .constant("nan", Value::Float(f64::NAN))
+        .function("sqrt", 1, sqrt)
+        .function("exp", 1, exp)
+        .function("log", Signature::fixed(1).optional(1), log)
+        .function("log2", 1, log2)
+        .function("log10", 1, log10)
+        .function("sin", 1, sin)
+        .function("cos", 1, cos)
+        .function("tan", 1, tan)
+        .function("asin", 1, asin)
+        .function("acos", 1, acos)
+        .function("atan", 1, atan)
+        .function("atan2", 2, atan2)
+        .function("pow", 2, pow)
+        .function("floor", 1, floor)
+        .function("ceil", 1, ceil)
+        .function("round", Signature::fixed(1).optional(1), round)
+        .function("abs", 1, abs)
+        .function("min", Signature::variadic(1), min)
+        .function("max", Signature::variadic(1), max);
+    natives.register_module(module);
+}
+
+/// NaN from numbers is outside the domain, infinity from finite numbers overflowed
+fn checked(name: &str, args: &[f64], result: f64) -> Result<Value, RuntimeError> {
+    let error = |kind: &str| {
+        let args = args.iter().map(|x| format!("{:?}", x)).collect::<Vec<_>>().join(", ");
+        RuntimeError::new(ErrorKind::Value, format!("math {} error in {}({})", kind, name, args))
+    };
+    if result.is_nan() && !args.iter().any(|x| x.is_nan()) {
+        return Err(error("domain"));
+    }
+    if result.is_infinite() && args.iter().all(|x| x.is_finite()) {
+        return Err(error("range"));
+    }
+    Ok(Value::Float(result))
+}
+
+macro_rules! float_functions {
+    ($($name:ident),*) => {$(
+        fn $name(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+            let x = f64::from_value(&args[0])?;
+            checked(stringify!($name), &[x], x.$name())
+        }
+    )*};
+}
+
+float_functions!(sqrt, exp, log2, log10, sin, cos, tan, asin, acos, atan);
+
+/// Natural logarithm, or in `base` when given
+fn log(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let x = f64::from_value(&args[0])?;
+    match Option::<f64>::from_value(&args[1])? {
+        Some(base) => checked("log", &[x, base], x.ln() / base.ln()),
+        None => checked("log", &[x], x.ln()),
+    }
+}
+
+fn atan2(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let (y, x) = (f64::from_value(&args[0])?, f64::from_value(&args[1])?);
+    checked("atan2", &[y, x], y.atan2(x))
+}
+
+/// Exact for an int raised to a non-negative int, a float otherwise
+fn pow(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    match (&args[0], &args[1]) {
+        (base @ (Value::Integer(_) | Value::BigInt(_)), exponent @ Value::Integer(0..)) => base.clone().__pow__(exponent.clone()),
+        (x, y) => {
+            let (x, y) = (f64::from_value(x)?, f64::from_value(y)?);
+            checked("pow", &[x, y], x.powf(y))
+        }
+    }
+}
+
+/// Integral float converted to an int, ints are returned unchanged
+fn to_int(value: &Value, round: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Integer(_) | Value::BigInt(_) => Ok(value.clone()),
+        Value::Float(f) => Value::Float(round(*f)).__int__(10),
+        value => Err(type_error("float", value)),
+    }
+}
+
+fn floor(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    to_int(&args[0], f64::floor)
+}
+
+fn ceil(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    to_int(&args[0], f64::ceil)
+}
+
+/// Halves round to even, an int without `digits` and a float with them
+fn round(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    match (&args[0], Option::<i32>::from_value(&args[1])?) {
+        (Value::Float(f), Some(digits)) => {
+            let scale = 10f64.powi(digits);
+            let scaled = f * scale;
+            if !f.is_finite() {
+                Ok(Value::Float(*f))
+            } else if scale == 0.0 {
+                // Rounding to a power of ten too large for f64 leaves nothing
+                Ok(Value::Float(0.0f64.copysign(*f)))
+            } else if !scaled.is_finite() {
+                // Digits finer than the float can hold change nothing
+                Ok(Value::Float(*f))
+            } else {
+                Ok(Value::Float(scaled.round_ties_even() / scale))
+            }
+        }
+        (value, _) => to_int(value, f64::round_ties_even),
+    }
+}
+
+fn abs(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Float(f) => Ok(Value::Float(f.abs())),
+        value @ (Value::Integer(_) | Value::BigInt(_)) => {
+            if value.clone().__lt__(Value::Integer(0))?.as_bool() {
+                value.clone().__neg__()
+            } else {
+                Ok(value.clone())
+            }
+        }
+        value => Err(type_error("int or float", value)),
+    }
+}
+
+/// Candidates are the arguments, or the items of a single list or tuple argument
+fn extreme(name: &str, args: &[Value], better: fn(Value, Value) -> Result<Value, RuntimeError>) -> Result<Value, RuntimeError> {
+    let items = match args {
+        [Value::List(items)] => items.borrow().clone(),
+        [Value::Tuple(items)] => items.to_vec(),
+        args => args.to_vec(),
+    };
+    let mut items = items.into_iter();
+    let mut best = items.next().ok_or_else(|| {
+        RuntimeError::new(ErrorKind::Value, format!("{} of an empty sequence", name))
+    })?;
+    for item in items {
+        if better(item.clone(), best.clone())?.as_bool() {
+            best = item;
+        }
+    }
+    Ok(best)
+}
+
+fn min(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    extreme("min", args, Value::__lt__)
+}
+
+fn max(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    extreme("max", args, Value::__gt__)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::IntoValue;
+
+    fn call(function: fn(&mut VM, &[Value]) -> Result<Value, RuntimeError>, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        function(&mut VM::new(), &args)
+    }
+
+    #[test]
+    fn integers_and_floats() {
+        assert_eq!(call(pow, vec![2.into_value(), 100.into_value()]).unwrap().__repr__(), "1267650600228229401496703205376");
+        assert_eq!(call(pow, vec![2.into_value(), (-1).into_value()]).unwrap().__repr__(), "0.5");
+        assert_eq!(call(abs, vec![i64::MIN.into_value()]).unwrap().__repr__(), "9223372036854775808");
+        assert_eq!(call(round, vec![2.5.into_value(), Value::None]).unwrap().__repr__(), "2");
+        assert_eq!(call(round, vec![2.675.into_value(), 1.into_value()]).unwrap().__repr__(), "2.7");
+        assert_eq!(call(round, vec![1250.0.into_value(), (-2).into_value()]).unwrap().__repr__(), "1200.0");
+        assert_eq!(call(round, vec![1.5.into_value(), (-400).into_value()]).unwrap().__repr__(), "0.0");
+        assert_eq!(call(round, vec![(-1.5).into_value(), (-400).into_value()]).unwrap().__repr__(), "-0.0");
+        assert_eq!(call(round, vec![1.5.into_value(), 400.into_value()]).unwrap().__repr__(), "1.5");
+        assert_eq!(call(round, vec![1e300.into_value(), 10.into_value()]).unwrap().__repr__(), 1e300.into_value().__repr__());
+        assert_eq!(call(floor, vec![(-1.5).into_value()]).unwrap().__repr__(), "-2");
+        assert_eq!(call(max, vec![vec![1.5.into_value(), 3.into_value(), 2.into_value()].into_value()]).unwrap().__repr__(), "3");
+        assert_eq!(call(min, vec![3.into_value(), (-1).into_value()]).unwrap().__repr__(), "-1");
+    }
+
+    #[test]
+    fn domain_errors() {
+        assert_eq!(call(sqrt, vec![(-1).into_value()]).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(call(asin, vec![2.into_value()]).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(call(exp, vec![1000.into_value()]).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(call(ceil, vec![f64::INFINITY.into_value()]).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(call(min, vec![Value::new_list(vec![])]).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(call(sqrt, vec!["4".into_value()]).unwrap_err().kind, ErrorKind::Type);
+        assert!(matches!(call(sqrt, vec![f64::NAN.into_value()]), Ok(Value::Float(f)) if f.is_nan()));
+    }
+}
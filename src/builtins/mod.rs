@@ -0,0 +1,187 @@
+use crate::error::{ErrorKind, RuntimeError};
+use std::io::Write;
+
+mod fs;
+mod json;
+mod math;
+mod random;
+mod time;
+
+pub(crate) use random::Rng;
+pub(crate) use time::Clock;
+
+use crate::convert::{FromValue, IntoValue};
+use crate::native::{NativeModule, Natives, Signature};
+use crate::value::{EnumType, Value};
+use crate::vm::VM;
+
+/// Natives every script can call
+pub fn register(natives: &mut Natives) {
+    natives.register("print", Signature::variadic(0).keywords(&["sep", "end"]), print);
+    natives.register("eprint", Signature::variadic(0).keywords(&["sep", "end"]), eprint);
+    natives.register("repr", 1, repr);
+    natives.register("int", Signature::fixed(1).keywords(&["base"]), int);
+    natives.register("float", 1, float);
+    natives.register("str", 1, str);
+    natives.register("bool", 1, bool);
+    natives.register("type", 1, type_name);
+    natives.register("len", 1, len);
+    natives.register("Ok", 1, ok);
+    natives.register("Err", 1, err);
+    natives.register("assert", 1, assert);
+    natives.register("input", 1, input);
+    natives.register("read_line", 0, read_line);
+    natives.register("read_all", 0, read_all);
+
+    let sys = NativeModule::new("sys")
+        .constant("args", Value::new_list(vec![]))
+        .function("env", 1, env)
+        .function("exit", 1, exit);
+    natives.register_module(sys);
+
+    fs::register(natives);
+    json::register(natives);
+    math::register(natives);
+    random::register(natives);
+    time::register(natives);
+}
+
+/// Runtime errors reach `catch` blocks as this struct, `kind` is the error name
+/// such as `ZeroDivisionError`
+pub(crate) struct Error {
+    pub kind: String,
+    pub message: String,
+}
+
+crate::struct_value!(Error { kind, message });
+
+impl Error {
+    /// Value a `catch` block receives, thrown values are passed unchanged
+    pub fn caught(error: RuntimeError) -> Value {
+        match error.value {
+            Some(value) => value,
+            None => Error { kind: error.kind.name().to_string(), message: error.message }.into_value(),
+        }
+    }
+
+    /// Error raised by `throw`, a rethrown `Error` struct keeps its kind
+    pub fn thrown(value: Value) -> RuntimeError {
+        let (kind, message) = match Error::from_value(&value) {
+            Ok(error) => (ErrorKind::from_name(&error.kind).unwrap_or(ErrorKind::Thrown), error.message),
+            Err(_) => (ErrorKind::Thrown, value.__str__()),
+        };
+        RuntimeError { kind, message, value: Some(value) }
+    }
+}
+
+/// Display forms of the arguments joined by `sep`, default a space,
+/// and followed by `end`, default a newline
+fn format_print(args: &[Value]) -> Result<String, RuntimeError> {
+    let (values, options) = args.split_at(args.len() - 2);
+    let sep = Option::<String>::from_value(&options[0])?.unwrap_or_else(|| " ".to_string());
+    let end = Option::<String>::from_value(&options[1])?.unwrap_or_else(|| "\n".to_string());
+    let mut out = values.iter().map(Value::__str__).collect::<Vec<_>>().join(&sep);
+    out.push_str(&end);
+    Ok(out)
+}
+
+fn print(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(format_print(args)?.as_bytes()).and_then(|_| stdout.flush()).map_err(RuntimeError::io)?;
+    Ok(Value::None)
+}
+
+/// Same as `print` on standard error
+fn eprint(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    std::io::stderr().write_all(format_print(args)?.as_bytes()).map_err(RuntimeError::io)?;
+    Ok(Value::None)
+}
+
+/// Debug form of a value, strings are quoted
+fn repr(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(args[0].__repr__().into_value())
+}
+
+/// Integer from a number or a string, `base` applies to strings only
+fn int(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let base = Option::<u32>::from_value(&args[1])?.unwrap_or(10);
+    args[0].__int__(base)
+}
+
+fn float(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    args[0].__float__()
+}
+
+/// Display form of a value, as `print` shows it
+fn str(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(args[0].__str__().into_value())
+}
+
+/// 1 or 0 by truthiness
+fn bool(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(args[0].as_bool().into_value())
+}
+
+fn type_name(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(args[0].type_name().into_value())
+}
+
+fn len(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(args[0].__len__()?.into_value())
+}
+
+/// Prints the prompt without a newline and reads a line, none at end of input
+fn input(vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    print!("{}", args[0].__str__());
+    std::io::stdout().flush().map_err(RuntimeError::io)?;
+    read_line(vm, &[])
+}
+
+/// Next line without its terminator, none at end of input
+fn read_line(vm: &mut VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+    let line = vm.read_line()?;
+    if line.is_empty() {
+        return Ok(Value::None);
+    }
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    Ok(line.into_value())
+}
+
+/// Rest of the input, empty string at end of input
+fn read_all(vm: &mut VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(vm.read_all()?.into_value())
+}
+
+fn ok(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    EnumType::construct(&EnumType::result(), "Ok", args.to_vec())
+}
+
+fn err(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    EnumType::construct(&EnumType::result(), "Err", args.to_vec())
+}
+
+/// Fails unless the condition is a nonzero integer, used by `nightjar test`
+fn assert(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Integer(0) => Err(RuntimeError::new(ErrorKind::Assertion, "assertion failed")),
+        Value::Integer(_) | Value::BigInt(_) => Ok(Value::None),
+        value => Err(RuntimeError::new(ErrorKind::Type, format!("assert expects int, got '{}'", value.type_name()))),
+    }
+}
+
+/// Value of environment variable `name` or none when it is not set
+fn env(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let name = String::from_value(&args[0])?;
+    Ok(std::env::var(name).ok().into_value())
+}
+
+/// Exit statuses are limited to 0..=255 like process exit codes, others would be truncated.
+/// The script stops right away, `catch` and `finally` blocks it is inside of do not run
+fn exit(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let code = i64::from_value(&args[0])?;
+    if !(0..=255).contains(&code) {
+        return Err(RuntimeError::new(ErrorKind::Value, format!("exit status {} is not in 0..=255", code)));
+    }
+    Err(RuntimeError::new(ErrorKind::Exit(code as i32), format!("exit with status {}", code)))
+}
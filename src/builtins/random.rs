@@ -0,0 +1,154 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::convert::{type_error, FromValue, IntoValue};
+use crate::error::{ErrorKind, RuntimeError};
+use crate::native::{NativeModule, Natives};
+use crate::value::Value;
+use crate::vm::VM;
+
+/// `random` module, every VM has its own generator
+pub fn register(natives: &mut Natives) {
+    let module = NativeModule::new("random")
+        .function("seed", 1, seed)
+        .function("int", 2, int)
+        .function("float", 0, float)
+        .function("choice", 1, choice)
+        .function("shuffle", 1, shuffle);
+    natives.register_module(module);
+}
+
+/// xoshiro256** seeded through splitmix64, the same seed gives
+/// the same sequence on every platform
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut x = seed;
+        let mut splitmix = || {
+            x = x.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+        Rng { state: [splitmix(), splitmix(), splitmix(), splitmix()] }
+    }
+
+    /// Seeded from the system clock, for runs which need not be reproducible
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Rng::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// Uniform in `0..bound` without modulo bias, `bound` is not zero
+    pub fn below(&mut self, bound: u64) -> u64 {
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let product = self.next_u64() as u128 * bound as u128;
+            if (product as u64) >= threshold {
+                return (product >> 64) as u64;
+            }
+        }
+    }
+
+    /// Uniform in `[0, 1)` with 53 random bits
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Restarts the sequence, negative seeds are allowed
+fn seed(vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let seed = i64::from_value(&args[0])?;
+    vm.rng = Rng::new(seed as u64);
+    Ok(Value::None)
+}
+
+/// Integer between `a` and `b`, both included
+fn int(vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let (a, b) = (i64::from_value(&args[0])?, i64::from_value(&args[1])?);
+    if a > b {
+        return Err(RuntimeError::new(ErrorKind::Value, format!("empty range for random.int({}, {})", a, b)));
+    }
+    let span = (b as i128 - a as i128 + 1) as u128;
+    let offset = match u64::try_from(span) {
+        Ok(span) => vm.rng.below(span),
+        // The whole i64 range
+        Err(_) => vm.rng.next_u64(),
+    };
+    Ok(((a as i128 + offset as i128) as i64).into_value())
+}
+
+fn float(vm: &mut VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(vm.rng.next_f64().into_value())
+}
+
+/// Random item of a list or tuple, or character of a string
+fn choice(vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let items = match &args[0] {
+        Value::List(items) => items.borrow().clone(),
+        Value::Tuple(items) => items.to_vec(),
+        Value::String(s) => s.chars().map(|c| c.to_string().into_value()).collect(),
+        value => return Err(type_error("list, tuple or string", value)),
+    };
+    if items.is_empty() {
+        return Err(RuntimeError::new(ErrorKind::Value, "random.choice from an empty sequence"));
+    }
+    let index = vm.rng.below(items.len() as u64) as usize;
+    Ok(items[index].clone())
+}
+
+/// Shuffles a list in place
+fn shuffle(vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let Value::List(items) = &args[0] else {
+        return Err(type_error("list", &args[0]));
+    };
+    let mut items = items.borrow_mut();
+    for i in (1..items.len()).rev() {
+        let j = vm.rng.below(i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+    Ok(Value::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducible() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let first: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..4).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first, (0..4).map(|_| Rng::new(43).next_u64()).collect::<Vec<_>>());
+
+        let mut vm = VM::new();
+        seed(&mut vm, &[7.into_value()]).unwrap();
+        let rolls: Vec<Value> = (0..20).map(|_| int(&mut vm, &[1.into_value(), 6.into_value()]).unwrap()).collect();
+        assert!(rolls.iter().all(|roll| matches!(roll, Value::Integer(1..=6))));
+        seed(&mut vm, &[7.into_value()]).unwrap();
+        assert_eq!(int(&mut vm, &[1.into_value(), 6.into_value()]).unwrap().__repr__(), rolls[0].__repr__());
+
+        let full = [i64::MIN.into_value(), i64::MAX.into_value()];
+        assert!(int(&mut vm, &full).is_ok());
+        assert!(int(&mut vm, &[2.into_value(), 1.into_value()]).is_err());
+        assert!((0..100).map(|_| vm.rng.next_f64()).all(|f| (0.0..1.0).contains(&f)));
+    }
+}
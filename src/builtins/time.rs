@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::convert::{FromValue, IntoValue};
+use crate::error::{ErrorKind, RuntimeError};
+use crate::native::{NativeModule, Natives};
+use crate::value::Value;
+use crate::vm::VM;
+
+/// `time` module, times are float seconds
+pub fn register(natives: &mut Natives) {
+    let module = NativeModule::new("time")
+        .function("now", 0, now)
+        .function("monotonic", 0, monotonic)
+        .function("sleep", 1, sleep);
+    natives.register_module(module);
+}
+
+/// Time source of a VM. The virtual clock only moves when the script sleeps,
+/// which makes runs reproducible
+#[derive(Debug, Clone)]
+pub(crate) enum Clock {
+    System { start: Instant },
+    Virtual { epoch: f64, elapsed: f64 },
+}
+
+impl Clock {
+    pub fn system() -> Self {
+        Clock::System { start: Instant::now() }
+    }
+
+    /// Seconds since the Unix epoch
+    pub fn now(&self) -> f64 {
+        match self {
+            Clock::System { .. } => SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64()),
+            Clock::Virtual { epoch, elapsed } => epoch + elapsed,
+        }
+    }
+
+    /// Seconds since the VM started, never goes backwards
+    pub fn monotonic(&self) -> f64 {
+        match self {
+            Clock::System { start } => start.elapsed().as_secs_f64(),
+            Clock::Virtual { elapsed, .. } => *elapsed,
+        }
+    }
+
+    pub fn sleep(&mut self, length: Duration) {
+        match self {
+            Clock::System { .. } => std::thread::sleep(length),
+            Clock::Virtual { elapsed, .. } => *elapsed += length.as_secs_f64(),
+        }
+    }
+}
+
+fn now(vm: &mut VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(vm.clock.now().into_value())
+}
+
+fn monotonic(vm: &mut VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(vm.clock.monotonic().into_value())
+}
+
+fn sleep(vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let seconds = f64::from_value(&args[0])?;
+    if !(seconds >= 0.0 && seconds.is_finite()) {
+        return Err(RuntimeError::new(ErrorKind::Value, format!("sleep length must be a non-negative number, got {:?}", seconds)));
+    }
+    let length = Duration::try_from_secs_f64(seconds)
+        .map_err(|_| RuntimeError::new(ErrorKind::Value, format!("sleep length {:?} is too large", seconds)))?;
+    vm.clock.sleep(length);
+    Ok(Value::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleep_length() {
+        let mut vm = VM::new();
+        vm.set_virtual_clock(0.0);
+        sleep(&mut vm, &[1.5.into_value()]).unwrap();
+        assert_eq!(vm.clock.monotonic(), 1.5);
+
+        for seconds in [-1.0, f64::NAN, f64::INFINITY, 1e20] {
+            let err = sleep(&mut vm, &[seconds.into_value()]).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::Value);
+        }
+        assert_eq!(vm.clock.monotonic(), 1.5);
+    }
+}
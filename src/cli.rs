@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Nightjar programming language
+
+Usage:
+    nightjar [options] <path|-> [args...]    run a script, `-` reads it from stdin
+    nightjar -e <code> [args...]             run inline code
+    nightjar <command> [options]             run a command
+    nightjar                                 start the REPL
+
+Commands:
+    run [--vm] <path|-> [args...]    run a script, --vm prints the final VM state
+    check <path>...                  parse and compile without running
+    dis <path|->                     print bytecode
+    ast <path|->                     print syntax tree
+    fmt [--check] <path|->...        reindent files in place, --check only reports
+    test [path]...                   run test_* functions of test files, default is `.`
+    repl                             start the REPL
+
+Options:
+    -e <code>        evaluate code instead of a file
+    -h, --help       show this message
+    -V, --version    show version
+
+Arguments after `--` or after the script path are passed to the script.";
+
+/// Where the code to run comes from
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    File(PathBuf),
+    Stdin,
+    Inline(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run { source: Source, args: Vec<String>, vm_verbose: bool },
+    Check { sources: Vec<Source> },
+    Dis { source: Source },
+    Ast { source: Source },
+    Fmt { sources: Vec<Source>, check: bool },
+    Test { paths: Vec<PathBuf> },
+    Repl,
+    Help,
+    Version,
+}
+
+/// Parses arguments without the program name, errors are usage messages
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let Some(first) = args.first() else {
+        return Ok(Command::Repl);
+    };
+    let rest = &args[1..];
+    match first.as_str() {
+        "-h" | "--help" | "help" => Ok(Command::Help),
+        "-V" | "--version" => Ok(Command::Version),
+        "repl" => expect_no_args(rest).map(|_| Command::Repl),
+        "run" => parse_run(rest),
+        "check" => Ok(Command::Check { sources: parse_sources(rest, 1)? }),
+        "dis" => Ok(Command::Dis { source: parse_single(rest)? }),
+        "ast" => Ok(Command::Ast { source: parse_single(rest)? }),
+        "fmt" => {
+            let check = rest.first().is_some_and(|arg| arg == "--check");
+            let sources = parse_sources(&rest[check as usize..], 1)?;
+            Ok(Command::Fmt { sources, check })
+        }
+        "test" => {
+            let sources = parse_sources(rest, 0)?;
+            let mut paths = vec![];
+            for source in sources {
+                match source {
+                    Source::File(path) => paths.push(path),
+                    _ => return Err("test takes file or directory paths".to_string()),
+                }
+            }
+            if paths.is_empty() {
+                paths.push(PathBuf::from("."));
+            }
+            Ok(Command::Test { paths })
+        }
+        _ => parse_run(args),
+    }
+}
+
+fn parse_run(args: &[String]) -> Result<Command, String> {
+    let mut vm_verbose = false;
+    let mut args = args.iter();
+    let source = loop {
+        match args.next().map(String::as_str) {
+            Some("--vm") => vm_verbose = true,
+            Some("-e") => match args.next() {
+                Some(code) => break Source::Inline(code.clone()),
+                None => return Err("-e expects code".to_string()),
+            },
+            Some("-") => break Source::Stdin,
+            Some(arg) if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            Some(path) => break Source::File(PathBuf::from(path)),
+            None => return Err("missing script path".to_string()),
+        }
+    };
+    let args: Vec<String> = args.cloned().collect();
+    let args = match args.first().map(String::as_str) {
+        Some("--") => args[1..].to_vec(),
+        _ => args,
+    };
+    Ok(Command::Run { source, args, vm_verbose })
+}
+
+fn parse_sources(args: &[String], min: usize) -> Result<Vec<Source>, String> {
+    let mut sources = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "-" => sources.push(Source::Stdin),
+            arg if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            path => sources.push(Source::File(PathBuf::from(path))),
+        }
+    }
+    if sources.len() < min {
+        return Err("missing script path".to_string());
+    }
+    Ok(sources)
+}
+
+fn parse_single(args: &[String]) -> Result<Source, String> {
+    let mut sources = parse_sources(args, 1)?;
+    if sources.len() > 1 {
+        return Err("expected a single script path".to_string());
+    }
+    Ok(sources.remove(0))
+}
+
+fn expect_no_args(args: &[String]) -> Result<(), String> {
+    match args.first() {
+        Some(arg) => Err(format!("unexpected argument {}", arg)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse(&[]), Ok(Command::Repl));
+        assert_eq!(parse(&["--version"]), Ok(Command::Version));
+        assert_eq!(parse(&["dis", "a.njar"]), Ok(Command::Dis { source: Source::File("a.njar".into()) }));
+        assert_eq!(parse(&["fmt", "--check", "-"]), Ok(Command::Fmt { sources: vec![Source::Stdin], check: true }));
+        assert_eq!(parse(&["test"]), Ok(Command::Test { paths: vec![".".into()] }));
+        assert!(parse(&["dis", "--dis"]).is_err());
+        assert!(parse(&["check"]).is_err());
+    }
+
+    #[test]
+    fn script_args() {
+        let run = |source, args: &[&str], vm_verbose| Ok(Command::Run {
+            source, args: args.iter().map(|arg| arg.to_string()).collect(), vm_verbose,
+        });
+        assert_eq!(parse(&["a.njar", "x", "-y"]), run(Source::File("a.njar".into()), &["x", "-y"], false));
+        assert_eq!(parse(&["run", "--vm", "-", "--", "--z"]), run(Source::Stdin, &["--z"], true));
+        assert_eq!(parse(&["-e", "print(1)", "--", "a"]), run(Source::Inline("print(1)".into()), &["a"], false));
+        assert!(parse(&["--dis", "a.njar"]).is_err());
+        assert!(parse(&["-e"]).is_err());
+    }
+}
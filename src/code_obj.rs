@@ -1,44 +1,110 @@
+use std::fmt;
+
 use crate::value::Value;
 use crate::opcode::OpCode;
 
-#[derive(Debug)]
+/// Entry of the exception handler table, code in `start..end` jumps to `target`
+/// when an error reaches it, with the stack cut down to `depth` values above
+/// the locals and the exception pushed
+#[derive(Debug, Clone, Copy)]
+pub struct Handler {
+    pub start: u16,
+    pub end: u16,
+    pub target: u16,
+    pub depth: u16,
+}
+
 pub struct CodeObject {
+    pub name: String,
+    pub arity: usize,
+    /// Local slots including parameters, always 0 for module code which uses globals
+    pub nlocals: usize,
     pub code: Vec<u8>,
     pub consts: Vec<Value>,
+    /// Global names of module code, indexed by `push_var`/`store_var` position
+    pub names: Vec<String>,
+    /// Globals declared `pub`, the only members importers can reach
+    pub exports: Vec<String>,
+    /// Innermost handlers come first, see `VM::unwind`
+    pub handlers: Vec<Handler>,
 }
 
 impl CodeObject {
     pub fn new_empty() -> Self {
-        CodeObject { code: vec![], consts: vec![] }
+        CodeObject::new_module("<module>")
+    }
+
+    pub fn new_module(name: &str) -> Self {
+        CodeObject::new_function(name, 0)
+    }
+
+    pub fn new_function(name: &str, arity: usize) -> Self {
+        CodeObject { name: name.to_string(), arity, nlocals: 0, code: vec![], consts: vec![], names: vec![], exports: vec![], handlers: vec![] }
+    }
+
+    pub fn read_operand(&self, pos: usize) -> u16 {
+        u16::from_le_bytes([self.code[pos], self.code[pos + 1]])
+    }
+
+    /// Handler covering the instruction which ends right before or at `pc`,
+    /// the program counter has already moved past it when an error is raised
+    pub fn find_handler(&self, pc: usize) -> Option<&Handler> {
+        self.handlers.iter().find(|handler| (handler.start as usize) < pc && pc <= handler.end as usize)
+    }
+}
+
+impl fmt::Debug for CodeObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<code {}>", self.name)
     }
 }
 
 
 pub fn dis(co: &CodeObject) {
-    println!("Disassembler:");
-
-    let mut prev = OpCode::halt;
-    for (ind, code) in co.code.iter().enumerate() {
-        if prev == OpCode::push_const {
-            let value = &co.consts[*code as usize];
-            println!("{}: <const {:?} at {}>", ind, value, code);
-            prev = OpCode::halt
-        } else if prev == OpCode::push_var {
-            println!("{}: <const at {}>", ind, code);
-            prev = OpCode::halt
-        } else if prev == OpCode::store_var {
-            println!("{}: <pos {}>", ind, code);
-            prev = OpCode::halt
-        } else if prev == OpCode::jmp {
-            println!("{}: <jmp pos {}>", ind, code);
-            prev = OpCode::halt
-        } else if prev == OpCode::pop_jmp_ifzero {
-            println!("{}: <jmp pos {}>", ind, code);
-            prev = OpCode::halt
-        } else {
-            let opcode = OpCode::from_u8(*code);
-            println!("{}: {:?}", ind, opcode);
-            prev = opcode
+    println!("Disassembler: {}", co.name);
+
+    let mut ind = 0;
+    while ind < co.code.len() {
+        let opcode = OpCode::from_u8(co.code[ind]);
+        let operands: Vec<u16> = (0..opcode.operand_count())
+            .map(|i| co.read_operand(ind + 1 + 2 * i))
+            .collect();
+
+        match (opcode, operands.as_slice()) {
+            (OpCode::push_const, [index]) => {
+                println!("{}: {:?} <const {:?} at {}>", ind, opcode, co.consts[*index as usize], index)
+            }
+            (OpCode::format_value, [index]) => {
+                println!("{}: {:?} <spec {:?} at {}>", ind, opcode, co.consts[*index as usize], index)
+            }
+            (OpCode::get_attr | OpCode::set_attr | OpCode::import_module | OpCode::build_struct, [index]) => {
+                println!("{}: {:?} <{:?} at {}>", ind, opcode, co.consts[*index as usize], index)
+            }
+            (OpCode::call_method, [name, argc]) => {
+                println!("{}: {:?} <method {:?}, argc {}>", ind, opcode, co.consts[*name as usize], argc)
+            }
+            (OpCode::call_native, [index, argc]) => {
+                println!("{}: {:?} <native {}, argc {}>", ind, opcode, index, argc)
+            }
+            (OpCode::push_var | OpCode::store_var | OpCode::push_local | OpCode::store_local, [pos]) => {
+                println!("{}: {:?} <pos {}>", ind, opcode, pos)
+            }
+            (OpCode::jmp | OpCode::pop_jmp_ifzero | OpCode::for_iter, [pos]) => {
+                println!("{}: {:?} <jmp pos {}>", ind, opcode, pos)
+            }
+            (OpCode::unwrap_ok, [pos, depth]) => {
+                println!("{}: {:?} <jmp pos {}, depth {}>", ind, opcode, pos, depth)
+            }
+            (_, [count]) => println!("{}: {:?} <count {}>", ind, opcode, count),
+            _ => println!("{}: {:?}", ind, opcode),
+        }
+        ind += 1 + 2 * operands.len();
+    }
+
+    if !co.handlers.is_empty() {
+        println!("Handlers:");
+        for handler in co.handlers.iter() {
+            println!("{}..{} -> {}, depth {}", handler.start, handler.end, handler.target, handler.depth);
         }
     }
 
@@ -47,4 +113,10 @@ pub fn dis(co: &CodeObject) {
         println!("{}: {:?}", ind, value);
     }
     println!();
+
+    for value in co.consts.iter() {
+        if let Value::Code(code) = value {
+            dis(code);
+        }
+    }
 }
@@ -1,9 +1,16 @@
 use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
 
 use crate::opcode::OpCode;
 use crate::ast;
-use crate::code_obj::CodeObject;
-use crate::value::Value;
+use crate::code_obj::{CodeObject, Handler};
+use crate::error::CompileError;
+use crate::loader::Loader;
+use crate::native::{Natives, Signature};
+use crate::value::{EnumType, FieldDef, StructType, Value};
+
+type CompileResult = Result<(), CompileError>;
 
 
 struct Vartable {
@@ -17,130 +24,897 @@ impl Vartable {
 
     fn insert(&mut self, name: &str) {
         let pos = self.table.len();
-        self.table.insert(name.to_string(), pos);
+        self.table.entry(name.to_string()).or_insert(pos);
+    }
+
+    fn get(&self, name: &str) -> Option<u16> {
+        self.table.get(name).map(|pos| *pos as u16)
     }
 
-    fn get(&self, name: &str) -> u8 {
-        *self.table.get(name).unwrap() as u8
+    fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Names ordered by position
+    fn names(&self) -> Vec<String> {
+        let mut names = vec![String::new(); self.table.len()];
+        for (name, pos) in self.table.iter() {
+            names[*pos] = name.clone();
+        }
+        names
     }
 }
 
-struct CompilerState {
-    vartable: Vartable,
+/// Protected code of a `try` statement being compiled. It is split in ranges
+/// so code inlined from `finally` blocks on the way out is left uncovered
+struct TryContext {
+    /// Block to run when leaving through `return`, `None` for a catch region
+    finally: Option<Vec<ast::Stmt>>,
+    /// Values kept on the stack above the locals when the statement starts
+    depth: u16,
+    /// Start of the open range
+    start: u16,
+    ranges: Vec<(u16, u16)>,
+}
+
+impl TryContext {
+    fn new(finally: Option<Vec<ast::Stmt>>, depth: usize, start: u16) -> Self {
+        TryContext { finally, depth: depth as u16, start, ranges: vec![] }
+    }
+
+    fn close(&mut self, end: u16) {
+        if self.start < end {
+            self.ranges.push((self.start, end));
+        }
+    }
+}
+
+/// Loop being compiled, `break` and `continue` jump out of it or to its start
+struct LoopContext {
+    label: Option<String>,
+    /// Target of `continue`
+    start: u16,
+    /// Jumps of `break` to patch once the loop end is known
+    breaks: Vec<usize>,
+    /// Stack depth outside of the loop and inside of its body,
+    /// they differ by the iterator of a `for` loop
+    outer_depth: usize,
+    body_depth: usize,
+    /// Enclosing `try` statements when the loop starts
+    trys: usize,
+}
+
+struct CompilerState<'a> {
+    globals: Vartable,
+    /// Locals of the function being compiled, `None` at module level
+    locals: Option<Vartable>,
     co: CodeObject,
+    loader: &'a mut Loader,
+    natives: &'a Natives,
+    /// File of the module being compiled, imports are resolved relative to it
+    path: &'a Path,
+    /// Name of the module the code runs in, owner of declared struct types
+    module_name: String,
+    /// Imported modules by alias, used to reject access to their private members
+    imports: HashMap<String, (String, Rc<CodeObject>)>,
+    /// Imported native modules by alias, their functions are called with `call_native`
+    native_imports: HashMap<String, String>,
+    /// Values the enclosing statements keep on the stack, such as loop iterators
+    stack_depth: usize,
+    /// Enclosing `try` statements of the current function, innermost last
+    trys: Vec<TryContext>,
+    /// Declared return type of the function being compiled, `?` needs a `Result`
+    return_type: Option<String>,
+    /// Enclosing loops of the current function, innermost last
+    loops: Vec<LoopContext>,
 }
 
-impl CompilerState {
-    fn new_empty() -> Self {
-        CompilerState { vartable: Vartable::new(), co: CodeObject::new_empty() }
+impl<'a> CompilerState<'a> {
+    fn new_empty(loader: &'a mut Loader, natives: &'a Natives, path: &'a Path, name: &str) -> Self {
+        let co = CodeObject::new_module(name);
+        let module_name = name.to_string();
+        CompilerState {
+            globals: Vartable::new(), locals: None, co, loader, natives, path, module_name,
+            imports: HashMap::new(), native_imports: HashMap::new(), stack_depth: 0, trys: vec![],
+            return_type: None, loops: vec![],
+        }
     }
 }
 
-const OVERRIDE_LATER: u8 = 111;
+const OVERRIDE_LATER: u16 = u16::MAX;
+
 
+pub fn compile_module(module: &ast::Module, loader: &mut Loader, natives: &Natives, path: &Path) -> Result<CodeObject, CompileError> {
+    let mut cs = CompilerState::new_empty(loader, natives, path, &path.display().to_string());
+    compile_body(&mut cs, module, false)?;
+    Ok(cs.co)
+}
 
-pub fn compile_module(module: &ast::Module) -> CodeObject {
-    let mut cs = CompilerState::new_empty();
+/// Compiles code that runs inside module `name` which already has globals `names`,
+/// they keep their positions and new globals are appended after them.
+/// Value of a trailing expression statement is left for `halt` to return
+pub fn compile_into(
+    module: &ast::Module,
+    loader: &mut Loader,
+    natives: &Natives,
+    path: &Path,
+    name: &str,
+    names: &[String],
+) -> Result<CodeObject, CompileError> {
+    let mut cs = CompilerState::new_empty(loader, natives, path, name);
+    for name in names {
+        cs.globals.insert(name);
+    }
+    compile_body(&mut cs, module, true)?;
+    Ok(cs.co)
+}
 
+fn compile_body(cs: &mut CompilerState, module: &ast::Module, keep_result: bool) -> CompileResult {
+    // Top level names are known upfront, so functions can refer to
+    // each other and to globals declared after them
     for stmt in module.stmts.iter() {
-        compile_stmt(&mut cs, stmt);
+        match stmt {
+            ast::Stmt::FunDecl { name, public, .. }
+            | ast::Stmt::StructDecl { name, public, .. }
+            | ast::Stmt::EnumDecl { name, public, .. } => {
+                cs.globals.insert(name);
+                if *public {
+                    cs.co.exports.push(name.clone());
+                }
+            }
+            ast::Stmt::Import { alias, .. } => cs.globals.insert(alias),
+            ast::Stmt::VarDecl { pattern, public, .. } => {
+                let mut names = vec![];
+                pattern_names(pattern, &mut names);
+                for name in names {
+                    cs.globals.insert(&name);
+                    if *public {
+                        cs.co.exports.push(name);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Functions and types are defined before any other statement runs
+    let is_definition = |stmt: &ast::Stmt| matches!(
+        stmt,
+        ast::Stmt::FunDecl { .. } | ast::Stmt::StructDecl { .. } | ast::Stmt::EnumDecl { .. }
+    );
+    for stmt in module.stmts.iter().filter(|stmt| is_definition(stmt)) {
+        compile_stmt(cs, stmt)?;
+    }
+    let mut stmts = module.stmts.iter().filter(|stmt| !is_definition(stmt)).peekable();
+    while let Some(stmt) = stmts.next() {
+        match stmt {
+            ast::Stmt::Expr(expr) if keep_result && stmts.peek().is_none() => compile_expr(cs, expr)?,
+            stmt => compile_stmt(cs, stmt)?,
+        }
     }
     cs.co.code.push(OpCode::halt as u8);
-    cs.co
+    cs.co.names = cs.globals.names();
+    Ok(())
+}
+
+fn pattern_names(pattern: &ast::Pattern, names: &mut Vec<String>) {
+    match pattern {
+        ast::Pattern::Ident(name) => names.push(name.clone()),
+        ast::Pattern::Wildcard => {}
+        ast::Pattern::Tuple(items) => items.iter().for_each(|item| pattern_names(item, names)),
+    }
 }
 
 
-fn compile_stmt(cs: &mut CompilerState, stmt: &ast::Stmt) {
+fn push_arg(cs: &mut CompilerState, opcode: OpCode, arg: u16) {
+    cs.co.code.push(opcode as u8);
+    cs.co.code.extend(arg.to_le_bytes());
+}
+
+/// Emits a jump with unknown target, returns position of the target to patch
+fn push_jump(cs: &mut CompilerState, opcode: OpCode) -> usize {
+    push_arg(cs, opcode, OVERRIDE_LATER);
+    cs.co.code.len() - 2
+}
+
+/// Points jump at `pos` to the current end of code
+fn patch_jump(cs: &mut CompilerState, pos: usize) {
+    let target = code_pos(cs);
+    cs.co.code[pos..pos + 2].copy_from_slice(&target.to_le_bytes());
+}
+
+fn code_pos(cs: &CompilerState) -> u16 {
+    u16::try_from(cs.co.code.len()).expect("Code object is too large")
+}
+
+
+fn compile_stmt(cs: &mut CompilerState, stmt: &ast::Stmt) -> CompileResult {
     match stmt {
-        ast::Stmt::Expr(expr) => compile_expr(cs, expr),
-        ast::Stmt::VarDecl { name, value } => compile_vardecl(cs, name, value),
+        ast::Stmt::Expr(expr) => {
+            compile_expr(cs, expr)?;
+            cs.co.code.push(OpCode::pop as u8);
+            Ok(())
+        }
+        ast::Stmt::Import { path, alias } => compile_import(cs, path, alias),
+        ast::Stmt::FunDecl { name, params, return_type, body, .. } => compile_fundecl(cs, name, params, return_type, body),
+        ast::Stmt::VarDecl { pattern, value, .. } => compile_vardecl(cs, pattern, value),
+        ast::Stmt::Assign { target, op, value } => compile_assign(cs, target, op.as_ref(), value),
+        ast::Stmt::StructDecl { name, fields, .. } => compile_structdecl(cs, name, fields),
+        ast::Stmt::EnumDecl { name, variants, .. } => compile_enumdecl(cs, name, variants),
+        ast::Stmt::Return(value) => compile_return(cs, value.as_ref()),
         ast::Stmt::If(if_stmt) => compile_if(cs, if_stmt),
-        _ => unimplemented!(),
+        ast::Stmt::While { label, test, body } => compile_while(cs, label, test, body),
+        ast::Stmt::For { label, pattern, iter, body } => compile_for(cs, label, pattern, iter, body),
+        ast::Stmt::Break(label) => compile_break(cs, label.as_deref()),
+        ast::Stmt::Continue(label) => compile_continue(cs, label.as_deref()),
+        ast::Stmt::Throw(value) => {
+            compile_expr(cs, value)?;
+            cs.co.code.push(OpCode::throw as u8);
+            Ok(())
+        }
+        ast::Stmt::Try(try_stmt) => compile_try(cs, try_stmt),
     }
 }
 
-fn compile_if(cs: &mut CompilerState, if_stmt: &ast::IfStmt) {
-    compile_expr(cs, &if_stmt.if_test);
-    cs.co.code.push(OpCode::pop_jmp_ifzero as u8);
-    let if_false_jmp_pos = cs.co.code.len();
-    cs.co.code.push(OVERRIDE_LATER);
+/// Statements nested in a function or control flow, only top level ones can be `pub`
+fn compile_block(cs: &mut CompilerState, stmts: &[ast::Stmt]) -> CompileResult {
+    stmts.iter().try_for_each(|stmt| {
+        let public = matches!(
+            stmt,
+            ast::Stmt::FunDecl { public: true, .. } | ast::Stmt::VarDecl { public: true, .. }
+            | ast::Stmt::StructDecl { public: true, .. } | ast::Stmt::EnumDecl { public: true, .. }
+        );
+        if public {
+            return Err(CompileError::new("pub is only allowed at module top level"));
+        }
+        compile_stmt(cs, stmt)
+    })
+}
 
-    for stmt in if_stmt.if_body.iter() {
-        compile_stmt(cs, stmt);
-    }
+fn compile_if(cs: &mut CompilerState, if_stmt: &ast::IfStmt) -> CompileResult {
+    compile_expr(cs, &if_stmt.if_test)?;
+    let if_false_jmp_pos = push_jump(cs, OpCode::pop_jmp_ifzero);
 
-    cs.co.code[if_false_jmp_pos] = cs.co.code.len() as u8;
+    compile_block(cs, &if_stmt.if_body)?;
 
     if let Some(else_body_block) = &if_stmt.else_body {
-        cs.co.code.push(OpCode::jmp as u8);
-        let jmp_pos = cs.co.code.len();
-        cs.co.code.push(OVERRIDE_LATER);
-        cs.co.code[if_false_jmp_pos] = cs.co.code.len() as u8;
+        let jmp_pos = push_jump(cs, OpCode::jmp);
+        patch_jump(cs, if_false_jmp_pos);
+
+        compile_block(cs, else_body_block)?;
+
+        patch_jump(cs, jmp_pos);
+    } else {
+        patch_jump(cs, if_false_jmp_pos);
+    }
+    Ok(())
+}
+
+fn compile_while(cs: &mut CompilerState, label: &Option<String>, test: &ast::Expr, body: &[ast::Stmt]) -> CompileResult {
+    let loop_start = code_pos(cs);
+    compile_expr(cs, test)?;
+    let loop_end_jmp_pos = push_jump(cs, OpCode::pop_jmp_ifzero);
 
-        for stmt in else_body_block.iter() {
-            compile_stmt(cs, stmt);
+    compile_loop_body(cs, label, loop_start, 0, body)?;
+    push_arg(cs, OpCode::jmp, loop_start);
+    patch_jump(cs, loop_end_jmp_pos);
+    finish_loop(cs);
+    Ok(())
+}
+
+/// Iterator stays on the stack for the whole loop, `for_iter` pushes
+/// the next item or drops the iterator and jumps past the loop
+fn compile_for(cs: &mut CompilerState, label: &Option<String>, pattern: &ast::Pattern, iter: &ast::Expr, body: &[ast::Stmt]) -> CompileResult {
+    compile_expr(cs, iter)?;
+    cs.co.code.push(OpCode::get_iter as u8);
+
+    let loop_start = code_pos(cs);
+    let loop_end_jmp_pos = push_jump(cs, OpCode::for_iter);
+    compile_store_pattern(cs, pattern);
+
+    compile_loop_body(cs, label, loop_start, 1, body)?;
+    push_arg(cs, OpCode::jmp, loop_start);
+    patch_jump(cs, loop_end_jmp_pos);
+    finish_loop(cs);
+    Ok(())
+}
+
+/// Body of a loop which keeps `kept` values on the stack while it runs
+fn compile_loop_body(cs: &mut CompilerState, label: &Option<String>, start: u16, kept: usize, body: &[ast::Stmt]) -> CompileResult {
+    if let Some(label) = label {
+        if cs.loops.iter().any(|context| context.label.as_ref() == Some(label)) {
+            return Err(CompileError::new(format!("label '{} is already used by an enclosing loop", label)));
+        }
+    }
+    let outer_depth = cs.stack_depth;
+    cs.loops.push(LoopContext {
+        label: label.clone(), start, breaks: vec![], outer_depth, body_depth: outer_depth + kept, trys: cs.trys.len(),
+    });
+    cs.stack_depth += kept;
+    compile_block(cs, body)?;
+    cs.stack_depth -= kept;
+    Ok(())
+}
+
+/// Points `break` jumps of the innermost loop to the current end of code
+fn finish_loop(cs: &mut CompilerState) {
+    let context = cs.loops.pop().unwrap();
+    for jump in context.breaks {
+        patch_jump(cs, jump);
+    }
+}
+
+/// Innermost loop, or the enclosing one with `label`
+fn find_loop(cs: &CompilerState, statement: &str, label: Option<&str>) -> Result<usize, CompileError> {
+    let found = match label {
+        Some(label) => cs.loops.iter().rposition(|context| context.label.as_deref() == Some(label)),
+        None => cs.loops.len().checked_sub(1),
+    };
+    found.ok_or_else(|| match label {
+        Some(label) => CompileError::new(format!("{} to unknown loop label '{}", statement, label)),
+        None => CompileError::new(format!("{} outside of loop", statement)),
+    })
+}
+
+/// Iterators of the loops it leaves are dropped, the loop end is patched later
+fn compile_break(cs: &mut CompilerState, label: Option<&str>) -> CompileResult {
+    let index = find_loop(cs, "break", label)?;
+    let (keep, depth) = (cs.loops[index].trys, cs.loops[index].outer_depth);
+    compile_leave(cs, keep, |cs| {
+        for _ in depth..cs.stack_depth {
+            cs.co.code.push(OpCode::pop as u8);
+        }
+        let jump = push_jump(cs, OpCode::jmp);
+        cs.loops[index].breaks.push(jump);
+    })
+}
+
+/// Iterators of the inner loops it leaves are dropped, the one of the target loop is kept
+fn compile_continue(cs: &mut CompilerState, label: Option<&str>) -> CompileResult {
+    let index = find_loop(cs, "continue", label)?;
+    let (keep, depth, start) = (cs.loops[index].trys, cs.loops[index].body_depth, cs.loops[index].start);
+    compile_leave(cs, keep, |cs| {
+        for _ in depth..cs.stack_depth {
+            cs.co.code.push(OpCode::pop as u8);
+        }
+        push_arg(cs, OpCode::jmp, start);
+    })
+}
+
+/// The body jumps to the catch block on error, with the error on the stack.
+/// Finally blocks are inlined on every normal way out, and compiled once more
+/// as a handler which runs them and throws the error again
+fn compile_try(cs: &mut CompilerState, try_stmt: &ast::TryStmt) -> CompileResult {
+    let start = code_pos(cs);
+    if let Some(finally) = &try_stmt.finally {
+        cs.trys.push(TryContext::new(Some(finally.clone()), cs.stack_depth, start));
+    }
+    let mut end_jumps = vec![];
+
+    if let Some(catch) = &try_stmt.catch {
+        cs.trys.push(TryContext::new(None, cs.stack_depth, start));
+        compile_block(cs, &try_stmt.body)?;
+        let mut context = cs.trys.pop().unwrap();
+        context.close(code_pos(cs));
+
+        if let Some(finally) = &try_stmt.finally {
+            let end = code_pos(cs);
+            cs.trys.last_mut().unwrap().close(end);
+            compile_block(cs, finally)?;
+        }
+        end_jumps.push(push_jump(cs, OpCode::jmp));
+
+        let target = code_pos(cs);
+        push_handlers(cs, &context, target);
+        if try_stmt.finally.is_some() {
+            cs.trys.last_mut().unwrap().start = target;
+        }
+        match &catch.name {
+            Some(name) => compile_store_name(cs, name),
+            None => cs.co.code.push(OpCode::pop as u8),
         }
+        compile_block(cs, &catch.body)?;
+    } else {
+        compile_block(cs, &try_stmt.body)?;
+    }
+
+    if let Some(finally) = &try_stmt.finally {
+        let mut context = cs.trys.pop().unwrap();
+        context.close(code_pos(cs));
+        compile_block(cs, finally)?;
+        end_jumps.push(push_jump(cs, OpCode::jmp));
 
-        cs.co.code[jmp_pos] = cs.co.code.len() as u8;
+        let target = code_pos(cs);
+        push_handlers(cs, &context, target);
+        cs.stack_depth += 1;
+        compile_block(cs, finally)?;
+        cs.stack_depth -= 1;
+        cs.co.code.push(OpCode::throw as u8);
     }
+
+    for jump in end_jumps {
+        patch_jump(cs, jump);
+    }
+    Ok(())
 }
 
+/// Nested statements are compiled first, so their handlers come before
+fn push_handlers(cs: &mut CompilerState, context: &TryContext, target: u16) {
+    for &(start, end) in context.ranges.iter() {
+        cs.co.handlers.push(Handler { start, end, target, depth: context.depth });
+    }
+}
+
+
+/// Module is compiled right away, its body runs on first `import_module`
+fn compile_import(cs: &mut CompilerState, path: &str, alias: &str) -> CompileResult {
+    if let Some(module) = cs.natives.module(path) {
+        compile_const(cs, Value::Module(module.clone()));
+        compile_store_name(cs, alias);
+        cs.native_imports.insert(alias.to_string(), path.to_string());
+        return Ok(());
+    }
 
-fn compile_vardecl(cs: &mut CompilerState, name: &str, value: &ast::Expr) {
-    compile_expr(cs, value);
-    cs.vartable.insert(name);
-    cs.co.code.push(OpCode::store_var as u8);
-    cs.co.code.push(cs.vartable.get(name))
+    let module = cs.loader.load(path, cs.path, cs.natives)?;
+    cs.imports.insert(alias.to_string(), (path.to_string(), module.clone()));
+    let module_index = add_const(cs, Value::Code(module));
+    push_arg(cs, OpCode::import_module, module_index);
+    compile_store_name(cs, alias);
+    Ok(())
 }
 
+fn compile_fundecl(cs: &mut CompilerState, name: &str, params: &[String], return_type: &str, body: &[ast::Stmt]) -> CompileResult {
+    let outer_co = std::mem::replace(&mut cs.co, CodeObject::new_function(name, params.len()));
+    let outer_locals = cs.locals.replace(Vartable::new());
+    let outer_depth = std::mem::take(&mut cs.stack_depth);
+    let outer_trys = std::mem::take(&mut cs.trys);
+    let outer_return_type = cs.return_type.replace(return_type.to_string());
+    let outer_loops = std::mem::take(&mut cs.loops);
+
+    let locals = cs.locals.as_mut().unwrap();
+    for param in params {
+        locals.insert(param);
+    }
+    let result = compile_block(cs, body).and_then(|_| compile_return(cs, None));
 
-fn compile_expr(cs: &mut CompilerState, expr: &ast::Expr) {
+    cs.co.nlocals = cs.locals.as_ref().unwrap().len();
+    cs.locals = outer_locals;
+    cs.stack_depth = outer_depth;
+    cs.trys = outer_trys;
+    cs.return_type = outer_return_type;
+    cs.loops = outer_loops;
+    let function = std::mem::replace(&mut cs.co, outer_co);
+    result?;
+
+    compile_const(cs, Value::Code(Rc::new(function)));
+    cs.co.code.push(OpCode::make_function as u8);
+    compile_store_name(cs, name);
+    Ok(())
+}
+
+/// Struct type is a constant, the module it belongs to is recorded
+/// so private fields stay accessible only from there
+fn compile_structdecl(cs: &mut CompilerState, name: &str, fields: &[ast::Field]) -> CompileResult {
+    let fields = fields.iter().map(|field| FieldDef { name: field.name.clone(), public: field.public });
+    let ty = StructType { name: name.to_string(), module: cs.module_name.clone(), fields: fields.collect() };
+    compile_const(cs, Value::StructType(Rc::new(ty)));
+    compile_store_name(cs, name);
+    Ok(())
+}
+
+fn compile_enumdecl(cs: &mut CompilerState, name: &str, variants: &[ast::Variant]) -> CompileResult {
+    let variants = variants.iter().map(|variant| (variant.name.clone(), variant.fields.clone()));
+    let ty = EnumType { name: name.to_string(), variants: variants.collect() };
+    compile_const(cs, Value::EnumType(Rc::new(ty)));
+    compile_store_name(cs, name);
+    Ok(())
+}
+
+fn compile_return(cs: &mut CompilerState, value: Option<&ast::Expr>) -> CompileResult {
+    if cs.locals.is_none() {
+        return Err(CompileError::new("return outside of function"));
+    }
+    match value {
+        Some(value) => compile_expr(cs, value)?,
+        None => compile_const(cs, Value::None),
+    }
+    compile_ret(cs)
+}
+
+/// Returns the value on top of the stack
+fn compile_ret(cs: &mut CompilerState) -> CompileResult {
+    cs.stack_depth += 1;
+    let result = compile_leave(cs, 0, |cs| cs.co.code.push(OpCode::ret as u8));
+    cs.stack_depth -= 1;
+    result
+}
+
+/// Jumps out of the `try` statements after the first `keep` ones with `exit`.
+/// Their finally blocks run first, each one still covered by the statements around it
+fn compile_leave(cs: &mut CompilerState, keep: usize, exit: impl FnOnce(&mut CompilerState)) -> CompileResult {
+    let mut left = vec![];
+    while cs.trys.len() > keep {
+        let mut context = cs.trys.pop().unwrap();
+        context.close(code_pos(cs));
+        let finally = context.finally.clone();
+        left.push(context);
+        if let Some(finally) = finally {
+            compile_block(cs, &finally)?;
+        }
+    }
+    exit(cs);
+
+    let resume = code_pos(cs);
+    for mut context in left.into_iter().rev() {
+        context.start = resume;
+        cs.trys.push(context);
+    }
+    Ok(())
+}
+
+/// `Ok` is unwrapped in place, `Err` is returned as it is
+fn compile_propagate(cs: &mut CompilerState, expr: &ast::Expr) -> CompileResult {
+    match &cs.return_type {
+        None => return Err(CompileError::new("? outside of function")),
+        Some(ty) if ty != "Result" => {
+            return Err(CompileError::new(format!("? needs {} to return Result, not {}", cs.co.name, ty)));
+        }
+        Some(_) => {}
+    }
+    compile_expr(cs, expr)?;
+    let ok_jmp_pos = push_jump(cs, OpCode::unwrap_ok);
+    cs.co.code.extend((cs.stack_depth as u16).to_le_bytes());
+    compile_ret(cs)?;
+    patch_jump(cs, ok_jmp_pos);
+    Ok(())
+}
+
+
+fn compile_vardecl(cs: &mut CompilerState, pattern: &ast::Pattern, value: &ast::Expr) -> CompileResult {
+    compile_expr(cs, value)?;
+    compile_store_pattern(cs, pattern);
+    Ok(())
+}
+
+/// Base and index of the target are evaluated once. A compound assignment
+/// copies them to read the current value before storing the new one
+fn compile_assign(cs: &mut CompilerState, target: &ast::Target, op: Option<&ast::BinOp>, value: &ast::Expr) -> CompileResult {
+    let compile_value = |cs: &mut CompilerState| match op {
+        Some(op) => compile_expr(cs, value).and_then(|_| compile_binop(cs, op)),
+        None => compile_expr(cs, value),
+    };
+    match target {
+        ast::Target::Name(name) => {
+            if op.is_some() {
+                compile_load_name(cs, name)?;
+            }
+            compile_value(cs)?;
+            compile_assign_name(cs, name)
+        }
+        ast::Target::Attr { base, name } => {
+            compile_expr(cs, base)?;
+            let name_index = add_const(cs, Value::String(Box::new(name.clone())));
+            if op.is_some() {
+                cs.co.code.push(OpCode::copy as u8);
+                push_arg(cs, OpCode::get_attr, name_index);
+            }
+            compile_value(cs)?;
+            push_arg(cs, OpCode::set_attr, name_index);
+            Ok(())
+        }
+        ast::Target::Index { base, index } => {
+            compile_expr(cs, base)?;
+            compile_expr(cs, index)?;
+            if op.is_some() {
+                cs.co.code.push(OpCode::copy2 as u8);
+                cs.co.code.push(OpCode::get_item as u8);
+            }
+            compile_value(cs)?;
+            cs.co.code.push(OpCode::set_item as u8);
+            Ok(())
+        }
+    }
+}
+
+/// Unlike `let`, assignment needs an existing variable, which may be a global
+fn compile_assign_name(cs: &mut CompilerState, name: &str) -> CompileResult {
+    if let Some(pos) = cs.locals.as_ref().and_then(|locals| locals.get(name)) {
+        push_arg(cs, OpCode::store_local, pos);
+    } else if let Some(pos) = cs.globals.get(name) {
+        push_arg(cs, OpCode::store_var, pos);
+    } else {
+        return Err(CompileError::new(format!("assignment to undefined variable {}", name)));
+    }
+    Ok(())
+}
+
+/// Pops the top of the stack into pattern, tuples are unpacked
+/// with an arity check and bound left to right
+fn compile_store_pattern(cs: &mut CompilerState, pattern: &ast::Pattern) {
+    match pattern {
+        ast::Pattern::Ident(name) => compile_store_name(cs, name),
+        ast::Pattern::Wildcard => cs.co.code.push(OpCode::pop as u8),
+        ast::Pattern::Tuple(items) => {
+            push_arg(cs, OpCode::unpack, items.len() as u16);
+            for item in items {
+                compile_store_pattern(cs, item);
+            }
+        }
+    }
+}
+
+fn compile_store_name(cs: &mut CompilerState, name: &str) {
+    match &mut cs.locals {
+        Some(locals) => {
+            locals.insert(name);
+            let pos = locals.get(name).unwrap();
+            push_arg(cs, OpCode::store_local, pos);
+        }
+        None => {
+            cs.globals.insert(name);
+            let pos = cs.globals.get(name).unwrap();
+            push_arg(cs, OpCode::store_var, pos);
+        }
+    }
+}
+
+/// Variables shadow natives of the same name
+fn is_variable(cs: &CompilerState, name: &str) -> bool {
+    cs.locals.as_ref().and_then(|locals| locals.get(name)).is_some() || cs.globals.get(name).is_some()
+}
+
+fn compile_load_name(cs: &mut CompilerState, name: &str) -> CompileResult {
+    if let Some(pos) = cs.locals.as_ref().and_then(|locals| locals.get(name)) {
+        push_arg(cs, OpCode::push_local, pos);
+    } else if let Some(pos) = cs.globals.get(name) {
+        push_arg(cs, OpCode::push_var, pos);
+    } else {
+        return Err(CompileError::new(format!("undefined variable {}", name)));
+    }
+    Ok(())
+}
+
+/// Members of imported modules are known at compile time,
+/// so `alias.member` is checked to exist and to be public
+fn check_module_member(cs: &CompilerState, path: &str) -> CompileResult {
+    let mut segments = path.split('.');
+    let (alias, member) = match (segments.next(), segments.next()) {
+        (Some(alias), Some(member)) => (alias, member),
+        _ => return Ok(()),
+    };
+    if cs.locals.as_ref().and_then(|locals| locals.get(alias)).is_some() {
+        return Ok(());
+    }
+    if let Some(module_path) = cs.native_imports.get(alias) {
+        let module = cs.natives.module(module_path).unwrap();
+        if module.get(member).is_none() {
+            return Err(CompileError::new(format!("module {} has no member '{}'", module_path, member)));
+        }
+        return Ok(());
+    }
+    let Some((module_path, module)) = cs.imports.get(alias) else {
+        return Ok(());
+    };
+    if !module.names.iter().any(|name| name == member) {
+        return Err(CompileError::new(format!("module {} has no member '{}'", module_path, member)));
+    }
+    if !module.exports.iter().any(|name| name == member) {
+        return Err(CompileError::new(format!("'{}' is private in module {}", member, module_path)));
+    }
+    Ok(())
+}
+
+/// Loads `a.b.c` as variable `a` followed by attribute lookups
+fn compile_load_path(cs: &mut CompilerState, path: &str) -> CompileResult {
+    check_module_member(cs, path)?;
+    let mut segments = path.split('.');
+    compile_load_name(cs, segments.next().unwrap())?;
+    for attr in segments {
+        let attr_index = add_const(cs, Value::String(Box::new(attr.to_string())));
+        push_arg(cs, OpCode::get_attr, attr_index);
+    }
+    Ok(())
+}
+
+
+fn add_const(cs: &mut CompilerState, value: Value) -> u16 {
+    let const_index = cs.co.consts.len();
+    cs.co.consts.push(value);
+    const_index as u16
+}
+
+fn compile_const(cs: &mut CompilerState, value: Value) {
+    let const_index = add_const(cs, value);
+    push_arg(cs, OpCode::push_const, const_index);
+}
+
+/// Every part is turned into a string on the stack,
+/// then `build_string` joins all of them in one allocation
+fn compile_fstring(cs: &mut CompilerState, parts: &[ast::FStringPart]) -> CompileResult {
+    if let [ast::FStringPart::Literal(s)] = parts {
+        compile_const(cs, Value::String(Box::new(s.clone())));
+        return Ok(());
+    }
+
+    for part in parts {
+        match part {
+            ast::FStringPart::Literal(s) => compile_const(cs, Value::String(Box::new(s.clone()))),
+            ast::FStringPart::Field { expr, spec } => {
+                compile_expr(cs, expr)?;
+                let spec = spec.clone().unwrap_or_default();
+                let spec_index = add_const(cs, Value::String(Box::new(spec)));
+                push_arg(cs, OpCode::format_value, spec_index);
+            }
+        }
+    }
+    push_arg(cs, OpCode::build_string, parts.len() as u16);
+    Ok(())
+}
+
+fn compile_call(cs: &mut CompilerState, name: &str, args: &[ast::Expr], kwargs: &[(String, ast::Expr)]) -> CompileResult {
+    if let Some((receiver, method)) = name.rsplit_once('.') {
+        check_module_member(cs, name)?;
+        let native_module = match cs.locals.as_ref().and_then(|locals| locals.get(receiver)) {
+            Some(_) => None,
+            None => cs.native_imports.get(receiver),
+        };
+        if let Some(module_path) = native_module {
+            let qualified = format!("{}.{}", module_path, method);
+            if let Some((index, native)) = cs.natives.lookup(&qualified) {
+                let signature = native.signature.clone();
+                return compile_call_native(cs, index, &signature, &qualified, args, kwargs);
+            }
+        }
+        check_no_kwargs(name, kwargs)?;
+
+        compile_load_path(cs, receiver)?;
+        compile_exprs(cs, args)?;
+        let method_index = add_const(cs, Value::String(Box::new(method.to_string())));
+        push_arg(cs, OpCode::call_method, method_index);
+        cs.co.code.extend((args.len() as u16).to_le_bytes());
+        return Ok(());
+    }
+
+    if !is_variable(cs, name) {
+        if let Some((index, native)) = cs.natives.lookup(name) {
+            let signature = native.signature.clone();
+            return compile_call_native(cs, index, &signature, name, args, kwargs);
+        }
+    }
+    check_no_kwargs(name, kwargs)?;
+
+    compile_load_name(cs, name)?;
+    compile_exprs(cs, args)?;
+    push_arg(cs, OpCode::call, args.len() as u16);
+    Ok(())
+}
+
+/// Natives are resolved at compile time, so their signature is checked here.
+/// Keyword arguments are evaluated in the order the native declares them
+fn compile_call_native(
+    cs: &mut CompilerState, index: u16, signature: &Signature, name: &str,
+    args: &[ast::Expr], kwargs: &[(String, ast::Expr)],
+) -> CompileResult {
+    signature.check(name, args.len()).map_err(CompileError::new)?;
+    if let Some((keyword, _)) = kwargs.iter().find(|(keyword, _)| !signature.keywords.contains(keyword)) {
+        return Err(CompileError::new(format!("{} got an unexpected keyword argument '{}'", name, keyword)));
+    }
+    compile_exprs(cs, args)?;
+    let missing = signature.missing(args.len());
+    for _ in 0..missing {
+        let none = add_const(cs, Value::None);
+        push_arg(cs, OpCode::push_const, none);
+    }
+    for keyword in &signature.keywords {
+        match kwargs.iter().find(|(name, _)| name == keyword) {
+            Some((_, value)) => compile_expr(cs, value)?,
+            None => {
+                let none = add_const(cs, Value::None);
+                push_arg(cs, OpCode::push_const, none);
+            }
+        }
+    }
+    let argc = args.len() + missing + signature.keywords.len();
+    push_arg(cs, OpCode::call_native, index);
+    cs.co.code.extend((argc as u16).to_le_bytes());
+    Ok(())
+}
+
+/// Only natives declare keywords, scripted functions take positional arguments
+fn check_no_kwargs(name: &str, kwargs: &[(String, ast::Expr)]) -> CompileResult {
+    match kwargs.first() {
+        Some((keyword, _)) => Err(CompileError::new(format!("{} got an unexpected keyword argument '{}'", name, keyword))),
+        None => Ok(()),
+    }
+}
+
+fn compile_exprs(cs: &mut CompilerState, exprs: &[ast::Expr]) -> CompileResult {
+    exprs.iter().try_for_each(|expr| compile_expr(cs, expr))
+}
+
+
+fn compile_binop(cs: &mut CompilerState, op: &ast::BinOp) -> CompileResult {
+    let opcode = match op {
+        ast::BinOp::Add => OpCode::add,
+        ast::BinOp::Sub => OpCode::sub,
+        ast::BinOp::Mul => OpCode::mul,
+        ast::BinOp::Div => OpCode::div,
+        ast::BinOp::FloorDiv => OpCode::floor_div,
+        ast::BinOp::Rem => OpCode::rem,
+        ast::BinOp::Exp => OpCode::pow,
+        ast::BinOp::LShift => OpCode::lshift,
+        ast::BinOp::RShift => OpCode::rshift,
+        ast::BinOp::BitAnd => OpCode::bit_and,
+        ast::BinOp::BitOr => OpCode::bit_or,
+        ast::BinOp::BitXor => OpCode::bit_xor,
+        ast::BinOp::Eq => OpCode::et,
+        ast::BinOp::Ne => OpCode::ne,
+        ast::BinOp::Le => OpCode::le,
+        ast::BinOp::Ge => OpCode::ge,
+        ast::BinOp::Lt => OpCode::lt,
+        ast::BinOp::Gt => OpCode::gt,
+        op => return Err(CompileError::new(format!("operator {:?} is not supported yet", op))),
+    };
+    cs.co.code.push(opcode as u8);
+    Ok(())
+}
+
+fn compile_expr(cs: &mut CompilerState, expr: &ast::Expr) -> CompileResult {
     match expr {
         ast::Expr::Binary{lhs, op, rhs} => {
-            compile_expr(cs, &*lhs);
-            compile_expr(cs, &*rhs);
-            match op {
-                ast::BinOp::Add => cs.co.code.push(OpCode::add as u8),
-                ast::BinOp::Sub => cs.co.code.push(OpCode::sub as u8),
-                ast::BinOp::Mul => cs.co.code.push(OpCode::mul as u8),
-                ast::BinOp::Div => cs.co.code.push(OpCode::div as u8),
-                ast::BinOp::Eq => cs.co.code.push(OpCode::et as u8),
-                ast::BinOp::Ne => cs.co.code.push(OpCode::ne as u8),
-                ast::BinOp::Le => cs.co.code.push(OpCode::ne as u8),
-                ast::BinOp::Ge => cs.co.code.push(OpCode::ge as u8),
-                ast::BinOp::Lt => cs.co.code.push(OpCode::lt as u8),
-                ast::BinOp::Gt => cs.co.code.push(OpCode::gt as u8),
-                _ => unimplemented!(),
-            }
-        },
-        ast::Expr::Integer(x) => {
-            let value = Value::Integer(*x);
-            let const_index = cs.co.consts.len();
-            cs.co.consts.push(value);
-            cs.co.code.push(OpCode::push_const as u8);
-            cs.co.code.push(const_index as u8);
+            compile_expr(cs, lhs)?;
+            compile_expr(cs, rhs)?;
+            compile_binop(cs, op)?;
         },
-        ast::Expr::String(s) => {
-            let value = Value::String(Box::new(s.clone()));
-            let const_index = cs.co.consts.len();
-            cs.co.consts.push(value);
-            cs.co.code.push(OpCode::push_const as u8);
-            cs.co.code.push(const_index as u8);
-        }
-        ast::Expr::VarCall { name } => {
-            cs.co.code.push(OpCode::push_var as u8);
-            cs.co.code.push(cs.vartable.get(name));
-        }
-        ast::Expr::FunCall { name, args } => {
-            if name != "print" || args.len() != 1 {
-                panic!("Only one function supported")
+        ast::Expr::Index { base, index } => {
+            compile_expr(cs, base)?;
+            compile_expr(cs, index)?;
+            cs.co.code.push(OpCode::get_item as u8);
+        }
+        ast::Expr::Unary { op: ast::UnaryOp::Negative, expr } => {
+            compile_expr(cs, expr)?;
+            cs.co.code.push(OpCode::neg as u8);
+        }
+        ast::Expr::Unary { op: ast::UnaryOp::BitNot, expr } => {
+            compile_expr(cs, expr)?;
+            cs.co.code.push(OpCode::bit_not as u8);
+        }
+        ast::Expr::Unary { op, .. } => {
+            return Err(CompileError::new(format!("operator {:?} is not supported yet", op)))
+        }
+        ast::Expr::None => compile_const(cs, Value::None),
+        ast::Expr::Integer(x) => compile_const(cs, Value::Integer(*x)),
+        ast::Expr::Float(x) => compile_const(cs, Value::Float(*x)),
+        ast::Expr::String(s) => compile_const(cs, Value::String(Box::new(s.clone()))),
+        ast::Expr::FString(parts) => compile_fstring(cs, parts)?,
+        ast::Expr::Tuple(items) => {
+            compile_exprs(cs, items)?;
+            push_arg(cs, OpCode::build_tuple, items.len() as u16);
+        }
+        ast::Expr::List(items) => {
+            compile_exprs(cs, items)?;
+            push_arg(cs, OpCode::build_list, items.len() as u16);
+        }
+        ast::Expr::Map(entries) => {
+            for (key, value) in entries {
+                compile_expr(cs, key)?;
+                compile_expr(cs, value)?;
             }
-
-            for arg in args {
-                compile_expr(cs, arg);
+            push_arg(cs, OpCode::build_map, entries.len() as u16);
+        }
+        ast::Expr::VarCall { name } => compile_load_path(cs, name)?,
+        ast::Expr::FunCall { name, args, kwargs } => compile_call(cs, name, args, kwargs)?,
+        ast::Expr::StructInit { name, fields } => {
+            compile_load_path(cs, name)?;
+            let mut names = vec![];
+            for (field, value) in fields {
+                compile_expr(cs, value)?;
+                names.push(Value::String(Box::new(field.clone())));
             }
-            cs.co.code.push(OpCode::print as u8);
+            let names_index = add_const(cs, Value::Tuple(Rc::new(names)));
+            push_arg(cs, OpCode::build_struct, names_index);
         }
-        _ => unimplemented!(),
+        ast::Expr::Try(expr) => compile_propagate(cs, expr)?,
     }
-}
\ No newline at end of file
+    Ok(())
+}
@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::error::{ErrorKind, RuntimeError};
+use crate::value::Value;
+
+/// Module owning struct types declared by `struct_value!`, their fields are all public
+pub const HOST_MODULE: &str = "<host>";
+
+/// Conversion of Rust data into script values, it never fails
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// Conversion of script values into Rust data, mismatches are reported as `TypeError`
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError>;
+}
+
+/// `TypeError` for a value which is not of the `expected` type
+pub fn type_error(expected: &str, value: &Value) -> RuntimeError {
+    RuntimeError::new(ErrorKind::Type, format!("expected {}, got '{}'", expected, value.type_name()))
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        Ok(value.clone())
+    }
+}
+
+macro_rules! int_conversions {
+    ($($ty:ty),*) => {$(
+        impl IntoValue for $ty {
+            fn into_value(self) -> Value {
+                match i64::try_from(self) {
+                    Ok(v) => Value::Integer(v),
+                    Err(_) => Value::from_bigint(BigInt::from_u64(self as u64)),
+                }
+            }
+        }
+
+        impl FromValue for $ty {
+            fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+                let out_of_range = || {
+                    RuntimeError::new(ErrorKind::Value, format!("{} is out of range for {}", value.__repr__(), stringify!($ty)))
+                };
+                match value {
+                    Value::Integer(v) => <$ty>::try_from(*v).map_err(|_| out_of_range()),
+                    Value::BigInt(v) => v.to_u64().and_then(|v| <$ty>::try_from(v).ok()).ok_or_else(out_of_range),
+                    _ => Err(type_error("int", value)),
+                }
+            }
+        }
+    )*};
+}
+
+int_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+/// Integers are accepted where a float is expected
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Float(f) => Ok(*f),
+            Value::Integer(v) => Ok(*v as f64),
+            Value::BigInt(v) => Ok(v.to_f64()),
+            _ => Err(type_error("float", value)),
+        }
+    }
+}
+
+/// Scripts have no separate bool type, conditions produce 1 and 0
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Integer(self as i64)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Integer(v) => Ok(*v != 0),
+            _ => Err(type_error("int", value)),
+        }
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::None
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::None => Ok(()),
+            _ => Err(type_error("none", value)),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(Box::new(self))
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(Box::new(self.to_string()))
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::String(s) => Ok(*s.clone()),
+            _ => Err(type_error("string", value)),
+        }
+    }
+}
+
+/// `None` maps to `none`
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(value) => value.into_value(),
+            None => Value::None,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::None => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::new_list(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+/// Both lists and tuples convert into a `Vec`
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::List(items) => items.borrow().iter().map(T::from_value).collect(),
+            Value::Tuple(items) => items.iter().map(T::from_value).collect(),
+            _ => Err(type_error("list", value)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self) -> Value {
+        let entries: BTreeMap<String, Value> = self.into_iter().map(|(k, v)| (k, v.into_value())).collect();
+        Value::Map(Rc::new(RefCell::new(entries)))
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Map(entries) => {
+                entries.borrow().iter().map(|(k, v)| Ok((k.clone(), T::from_value(v)?))).collect()
+            }
+            _ => Err(type_error("map", value)),
+        }
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($($len:literal => ($($ty:ident $index:tt),*)),*) => {$(
+        impl<$($ty: IntoValue),*> IntoValue for ($($ty,)*) {
+            fn into_value(self) -> Value {
+                Value::Tuple(Rc::new(vec![$(self.$index.into_value()),*]))
+            }
+        }
+
+        impl<$($ty: FromValue),*> FromValue for ($($ty,)*) {
+            fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+                match value {
+                    Value::Tuple(items) if items.len() == $len => Ok(($($ty::from_value(&items[$index])?,)*)),
+                    _ => Err(type_error(concat!("tuple of ", $len), value)),
+                }
+            }
+        }
+    )*};
+}
+
+tuple_conversions! {
+    1 => (A 0),
+    2 => (A 0, B 1),
+    3 => (A 0, B 1, C 2),
+    4 => (A 0, B 1, C 2, D 3)
+}
+
+/// Maps a Rust struct with named fields to a script struct of the same name,
+/// every field type has to implement both conversion traits
+///
+/// ```
+/// struct Point { x: i64, y: i64 }
+/// nightjar::struct_value!(Point { x, y });
+/// ```
+#[macro_export]
+macro_rules! struct_value {
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::convert::IntoValue for $name {
+            fn into_value(self) -> $crate::Value {
+                use $crate::value::{FieldDef, Struct, StructType};
+                thread_local! {
+                    static TYPE: std::rc::Rc<StructType> = std::rc::Rc::new(StructType {
+                        name: stringify!($name).to_string(),
+                        module: $crate::convert::HOST_MODULE.to_string(),
+                        fields: vec![$(FieldDef { name: stringify!($field).to_string(), public: true }),*],
+                    });
+                }
+                let ty = TYPE.with(|ty| ty.clone());
+                let fields = vec![$($crate::convert::IntoValue::into_value(self.$field)),*];
+                $crate::Value::Struct(std::rc::Rc::new(Struct { ty, fields: std::cell::RefCell::new(fields) }))
+            }
+        }
+
+        /// Any struct value named the same with all the fields converts back
+        impl $crate::convert::FromValue for $name {
+            fn from_value(value: &$crate::Value) -> Result<Self, $crate::error::RuntimeError> {
+                match value {
+                    $crate::Value::Struct(instance) if instance.ty.name == stringify!($name) => {
+                        Ok($name {$(
+                            $field: {
+                                let field = instance.get(stringify!($field)).ok_or_else(|| {
+                                    $crate::convert::type_error(concat!(stringify!($name), " with field ", stringify!($field)), value)
+                                })?;
+                                $crate::convert::FromValue::from_value(&field)?
+                            },
+                        )*})
+                    }
+                    _ => Err($crate::convert::type_error(stringify!($name), value)),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: i64,
+        label: Option<String>,
+    }
+
+    crate::struct_value!(Point { x, label });
+
+    #[test]
+    fn round_trip() {
+        let items = vec![(1u8, "a".to_string()), (2, "b".to_string())];
+        assert_eq!(Vec::<(u8, String)>::from_value(&items.clone().into_value()).unwrap(), items);
+
+        let map: HashMap<String, Option<f64>> = [("a".to_string(), Some(1.5)), ("b".to_string(), None)].into();
+        assert_eq!(HashMap::<String, Option<f64>>::from_value(&map.clone().into_value()).unwrap(), map);
+
+        let point = Point { x: 3, label: None };
+        let value = Point { x: 3, label: None }.into_value();
+        assert_eq!(value.__repr__(), "Point { x: 3, label: none }");
+        assert_eq!(Point::from_value(&value).unwrap(), point);
+
+        assert_eq!(u64::from_value(&u64::MAX.into_value()).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn mismatches() {
+        assert!(i64::from_value(&"1".into_value()).is_err());
+        assert!(u8::from_value(&300.into_value()).is_err());
+        assert!(<(i64, i64)>::from_value(&(1, 2, 3).into_value()).is_err());
+        assert!(Point::from_value(&vec![1].into_value()).is_err());
+        assert!(matches!(String::from_value(&Value::None).unwrap_err().kind, ErrorKind::Type));
+    }
+}
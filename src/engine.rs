@@ -0,0 +1,457 @@
+use std::fs;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast;
+use crate::code_obj::CodeObject;
+use crate::error::{Error, ErrorKind, RuntimeError};
+use crate::loader::Loader;
+use crate::native::{NativeFn, NativeModule, Signature};
+use crate::parser;
+use crate::value::{Module, Value};
+use crate::vm::VM;
+
+/// Name of the module all evaluated code runs in
+pub const MAIN_MODULE: &str = "<main>";
+
+/// Embeddable interpreter, every `eval_*` call runs in the same main module,
+/// so globals defined by one call are visible to the next ones
+pub struct Engine {
+    loader: Loader,
+    vm: VM,
+    main: Rc<Module>,
+}
+
+impl Engine {
+    /// Engine resolving imports through `NIGHTJAR_PATH`
+    pub fn new() -> Self {
+        Engine::with_loader(Loader::from_env())
+    }
+
+    pub fn with_search_path(search_path: Vec<PathBuf>) -> Self {
+        Engine::with_loader(Loader::new(search_path))
+    }
+
+    fn with_loader(loader: Loader) -> Self {
+        let main = Rc::new(Module::new(&CodeObject::new_module(MAIN_MODULE)));
+        Engine { loader, vm: VM::new(), main }
+    }
+
+    /// Runs source code, imports are resolved relative to the working directory.
+    /// Returns the value of the last statement if it is an expression, otherwise none
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, Error> {
+        let module = parser::parse_source(source)?;
+        let co = self.compile(Path::new("<string>"), &module)?;
+        self.run(co)
+    }
+
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Value, Error> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let module = parser::parse_named_source(&source, &path.display().to_string())?;
+        let co = self.compile(path, &module)?;
+        self.run(co)
+    }
+
+    /// Compiles parsed code located at `path` to run in the main module
+    pub fn compile(&mut self, path: &Path, module: &ast::Module) -> Result<Rc<CodeObject>, Error> {
+        let names = self.main.names.borrow().clone();
+        Ok(self.loader.compile_into(path, module, &self.vm.natives, MAIN_MODULE, &names)?)
+    }
+
+    /// Runs code produced by `compile`
+    pub fn run(&mut self, co: Rc<CodeObject>) -> Result<Value, Error> {
+        Ok(self.vm.run_in(co, self.main.clone())?)
+    }
+
+    /// Calls global function `name` of the main module
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        let function = self.get_global(name).ok_or_else(|| {
+            RuntimeError::new(ErrorKind::Name, format!("undefined function {}", name))
+        })?;
+        Ok(self.vm.call_function(function, args)?)
+    }
+
+    /// Makes `function` callable from scripts compiled after this call,
+    /// `signature` is an arity or a `Signature`
+    pub fn register_function(&mut self, name: &str, signature: impl Into<Signature>, function: NativeFn) {
+        self.vm.natives.register(name, signature, function);
+    }
+
+    /// Makes `module` importable by scripts compiled after this call
+    pub fn register_module(&mut self, module: NativeModule) {
+        self.vm.natives.register_module(module);
+    }
+
+    /// Arguments the script sees as its command line
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.vm.set_args(args);
+    }
+
+    /// Replaces stdin as the source of `input`, `read_line` and `read_all`
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.vm.set_input(Box::new(input));
+    }
+
+    /// Runs with a virtual clock starting at the Unix epoch and a fixed random
+    /// seed, the same seed and input give the same run
+    pub fn set_deterministic(&mut self, seed: u64) {
+        self.vm.set_virtual_clock(0.0);
+        self.vm.seed_random(seed);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.main.get(name)
+    }
+
+    /// Sets global `name` of the main module, later code can refer to it
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.main.set(name, value);
+    }
+
+    /// Globals of the main module in declaration order
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let names = self.main.names.borrow();
+        names.iter().cloned().zip(self.main.globals.borrow().iter().cloned()).collect()
+    }
+
+    pub fn print_state(&self) {
+        self.vm.print_state();
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{FromValue, IntoValue};
+
+    #[test]
+    fn globals_persist_between_evals() {
+        let mut engine = Engine::new();
+        engine.eval_str("let x = 40").unwrap();
+        engine.set_global("y", Value::Integer(2));
+        engine.eval_str("let z = x + y").unwrap();
+        assert!(matches!(engine.get_global("z"), Some(Value::Integer(42))));
+        assert!(engine.get_global("w").is_none());
+        assert!(matches!(engine.eval_str("z - 2"), Ok(Value::Integer(40))));
+        assert!(matches!(engine.eval_str("let w = z"), Ok(Value::None)));
+    }
+
+    #[test]
+    fn call_function() {
+        let mut engine = Engine::new();
+        engine.eval_str("let base = 10\nfun add(a: int, b: int): int {\n    return base + a + b\n}").unwrap();
+        let result = engine.call_function("add", vec![Value::Integer(1), Value::Integer(2)]).unwrap();
+        assert!(matches!(result, Value::Integer(13)));
+
+        assert!(matches!(engine.call_function("add", vec![]), Err(Error::Runtime(_))));
+        assert!(matches!(engine.call_function("missing", vec![]), Err(Error::Runtime(_))));
+        assert!(matches!(engine.eval_str("let x = "), Err(Error::Parse(_))));
+        assert!(matches!(engine.eval_str("print(nope)"), Err(Error::Compile(_))));
+    }
+
+    #[test]
+    fn register_function() {
+        fn double(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+            args[0].clone().__mul__(Value::Integer(2))
+        }
+
+        let mut engine = Engine::new();
+        engine.register_function("double", 1, double);
+        engine.eval_str("let x = double(21)").unwrap();
+        assert!(matches!(engine.get_global("x"), Some(Value::Integer(42))));
+        assert!(matches!(engine.eval_str("double(1, 2)"), Err(Error::Compile(_))));
+
+        // Script definitions shadow natives
+        engine.eval_str("fun double(x: int): int {\n    return x\n}\nlet y = double(5)").unwrap();
+        assert!(matches!(engine.get_global("y"), Some(Value::Integer(5))));
+    }
+
+    #[test]
+    fn keyword_arguments() {
+        fn join(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+            let (values, sep) = args.split_at(args.len() - 1);
+            let sep = Option::<String>::from_value(&sep[0])?.unwrap_or_default();
+            Ok(values.iter().map(Value::__str__).collect::<Vec<_>>().join(&sep).into_value())
+        }
+
+        let mut engine = Engine::new();
+        engine.register_function("join", Signature::variadic(1).keywords(&["sep"]), join);
+        assert_eq!(engine.eval_str("join(1, \"a\", sep=\"-\")").unwrap().__str__(), "1-a");
+        assert_eq!(engine.eval_str("join(1, 2)").unwrap().__str__(), "12");
+        assert!(matches!(engine.eval_str("join()"), Err(Error::Compile(_))));
+        assert!(matches!(engine.eval_str("join(1, end=2)"), Err(Error::Compile(_))));
+        assert!(matches!(engine.eval_str("join(sep=1, 2)"), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn fs_module() {
+        let dir = std::env::temp_dir().join(format!("nightjar_fs_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lines.txt").display().to_string();
+
+        let mut engine = Engine::new();
+        engine.set_global("path", path.clone().into_value());
+        engine.eval_str("import fs").unwrap();
+        fs::write(&path, "a\r\nb\n").unwrap();
+        assert_eq!(engine.eval_str("fs.read_file(path)").unwrap().__str__(), "a\r\nb\n");
+        assert_eq!(engine.eval_str("let f = fs.open(path)\nfs.read_line(f)").unwrap().__str__(), "a");
+        assert_eq!(engine.eval_str("fs.read_line(f)").unwrap().__str__(), "b");
+        assert!(matches!(engine.eval_str("fs.read_line(f)"), Ok(Value::None)));
+        engine.eval_str("fs.remove(path)").unwrap();
+        assert!(matches!(engine.eval_str("fs.exists(path)"), Ok(Value::Integer(0))));
+        match engine.eval_str("fs.read_file(path)") {
+            Err(Error::Runtime(err)) => assert_eq!(err.kind, ErrorKind::IO),
+            result => panic!("expected IOError, got {:?}", result),
+        }
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn deterministic() {
+        let source = "import random\nimport time\nlet rolls = [random.int(1, 100), random.int(1, 100), random.float()]\ntime.sleep(90)\nlet now = time.now()";
+        let run = || {
+            let mut engine = Engine::new();
+            engine.set_deterministic(3);
+            engine.eval_str(source).unwrap();
+            (engine.get_global("rolls").unwrap().__repr__(), engine.get_global("now").unwrap().__repr__())
+        };
+        let (rolls, now) = run();
+        assert_eq!(run(), (rolls, now.clone()));
+        assert_eq!(now, "90.0");
+    }
+
+    #[test]
+    fn exceptions() {
+        fn push(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+            if let Value::List(items) = &args[0] {
+                items.borrow_mut().push(args[1].clone());
+            }
+            Ok(Value::None)
+        }
+
+        let mut engine = Engine::new();
+        engine.register_function("push", 2, push);
+        let source = "\
+fun first(items: list): int {
+    for item in items {
+        try {
+            return 10 / item
+        } catch e {
+            push(log, e.kind)
+        } finally {
+            push(log, item)
+        }
+    }
+    return -1
+}
+let log = []
+let found = first([0, 5])";
+        engine.eval_str(source).unwrap();
+        assert_eq!(engine.get_global("found").unwrap().__repr__(), "2.0");
+        assert_eq!(engine.get_global("log").unwrap().__repr__(), "[\"ZeroDivisionError\", 0, 5]");
+
+        engine.eval_str("try {\n    throw (1, 2)\n} catch e {\n    let caught = e\n}").unwrap();
+        assert_eq!(engine.get_global("caught").unwrap().__repr__(), "(1, 2)");
+        let err = engine.eval_str("try {\n    1 / 0\n} finally {\n    push(log, 3)\n}").unwrap_err();
+        assert!(matches!(err, Error::Runtime(RuntimeError { kind: ErrorKind::ZeroDivision, .. })));
+        assert_eq!(engine.get_global("log").unwrap().__repr__(), "[\"ZeroDivisionError\", 0, 5, 3]");
+        assert_eq!(engine.eval_str("throw \"oops\"").unwrap_err().to_string(), "Error: oops");
+        engine.eval_str("fun throwing(x: int): int {\n    return x + 1\n}").unwrap();
+        assert!(matches!(engine.eval_str("throwing(throwing(1))"), Ok(Value::Integer(3))));
+
+        // Runaway recursion is an error scripts can catch
+        engine.eval_str("fun deep(n: int): int {\n    return deep(n + 1)\n}").unwrap();
+        engine.eval_str("try {\n    deep(0)\n} catch e {\n    let depth_error = e.kind\n}").unwrap();
+        assert_eq!(engine.get_global("depth_error").unwrap().__repr__(), "\"RecursionError\"");
+        let err = engine.eval_str("deep(0)").unwrap_err();
+        assert!(matches!(err, Error::Runtime(RuntimeError { kind: ErrorKind::Recursion, .. })));
+
+        // Exiting skips handlers and finally blocks
+        let err = engine.eval_str("import sys\ntry {\n    sys.exit(2)\n} catch {\n}").unwrap_err();
+        assert_eq!(err.exit_status(), Some(2));
+        let err = engine.eval_str("try {\n    sys.exit(4)\n} finally {\n    push(log, 4)\n}").unwrap_err();
+        assert_eq!(err.exit_status(), Some(4));
+        assert_eq!(engine.get_global("log").unwrap().__repr__(), "[\"ZeroDivisionError\", 0, 5, 3]");
+    }
+
+    #[test]
+    fn result_propagation() {
+        let mut engine = Engine::new();
+        let source = "\
+fun half(x: int): Result {
+    if x % 2 == 1 {
+        return Err(x)
+    }
+    return Ok(x // 2)
+}
+fun sum_halves(items: list): Result {
+    let total = 0
+    for item in items {
+        try {
+            let total = total + half(item)?
+        } finally {
+            let cleaned = item
+        }
+    }
+    return Ok(total)
+}
+let good = sum_halves([2, 4])
+let bad = sum_halves([2, 3, 4])";
+        engine.eval_str(source).unwrap();
+        assert_eq!(engine.get_global("good").unwrap().__repr__(), "Ok(3)");
+        assert_eq!(engine.get_global("bad").unwrap().__repr__(), "Err(3)");
+        assert_eq!(engine.eval_str("bad == Err(3)").unwrap().__repr__(), "1");
+
+        let err = engine.eval_str("fun f(): Result {\n    return 1?\n}\nf()").unwrap_err();
+        assert_eq!(err.to_string(), "TypeError: expected Result, got 'int'");
+        assert!(matches!(engine.eval_str("fun g(): int {\n    return Ok(1)?\n}"), Err(Error::Compile(_))));
+        assert!(matches!(engine.eval_str("let y = Ok(1)?"), Err(Error::Compile(_))));
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let mut engine = Engine::new();
+        let source = "\
+fun find(rows: list, wanted: int): tuple {
+    let seen = 0
+    'outer: for row in rows {
+        for cell in row {
+            let seen = seen + 1
+            try {
+                if cell == wanted {
+                    break 'outer
+                }
+                if cell < 0 {
+                    continue 'outer
+                }
+            } catch {
+            }
+        }
+    }
+    return (seen, wanted)
+}
+let found = find([[1, -1, 9], [2, 3], [4]], 3)";
+        engine.eval_str(source).unwrap();
+        assert_eq!(engine.get_global("found").unwrap().__repr__(), "(4, 3)");
+
+        // Handlers of the loop body do not cover code after it
+        let err = engine.eval_str("for x in [1] {\n    try {\n        break\n    } catch {\n    }\n}\n1 / 0").unwrap_err();
+        assert!(matches!(err, Error::Runtime(RuntimeError { kind: ErrorKind::ZeroDivision, .. })));
+
+        assert!(matches!(engine.eval_str("break"), Err(Error::Compile(_))));
+        assert!(matches!(engine.eval_str("fun f(): none {\n    continue\n}"), Err(Error::Compile(_))));
+        assert!(matches!(engine.eval_str("'a: while 0 {\n    'a: while 0 {\n    }\n}"), Err(Error::Compile(_))));
+    }
+
+    #[test]
+    fn assignment() {
+        let mut engine = Engine::new();
+        let source = "\
+let calls = 0
+let items = [1, 2]
+let table = {\"a\": [0]}
+fun index(): int {
+    calls += 1
+    return -1
+}
+items[index()] += 10
+table[\"a\"][index() + 1] -= 3";
+        engine.eval_str(source).unwrap();
+        assert_eq!(engine.get_global("items").unwrap().__repr__(), "[1, 12]");
+        assert_eq!(engine.get_global("table").unwrap().__repr__(), "{\"a\": [-3]}");
+        assert!(matches!(engine.get_global("calls"), Some(Value::Integer(2))));
+
+        engine.eval_str("let bits = 3\nbits <<= 4\nbits |= 5\nbits &= 0x3c\nbits ^= 1\nbits >>= 1\nbits **= 2").unwrap();
+        assert!(matches!(engine.get_global("bits"), Some(Value::Integer(676))));
+        assert!(matches!(engine.eval_str("~bits"), Ok(Value::Integer(-677))));
+
+        assert!(matches!(engine.eval_str("missing = 1"), Err(Error::Compile(_))));
+        let err = engine.eval_str("let pair = (1, 2)\npair[0] = 3").unwrap_err();
+        assert!(matches!(err, Error::Runtime(RuntimeError { kind: ErrorKind::Type, .. })));
+        let err = engine.eval_str("items[2] = 0").unwrap_err();
+        assert!(matches!(err, Error::Runtime(RuntimeError { kind: ErrorKind::Index, .. })));
+    }
+
+    #[test]
+    fn register_module() {
+        fn query(_vm: &mut VM, args: &[Value]) -> Result<Value, RuntimeError> {
+            Ok(Value::new_list(vec![args[0].clone()]))
+        }
+
+        let mut engine = Engine::new();
+        engine.register_module(NativeModule::new("db").function("query", 1, query).constant("VERSION", Value::Integer(3)));
+        engine.eval_str("import db\nlet rows = db.query(db.VERSION)\nlet conn = db\nlet more = conn.query(1)").unwrap();
+        assert_eq!(engine.get_global("rows").unwrap().__repr__(), "[3]");
+        assert_eq!(engine.get_global("more").unwrap().__repr__(), "[1]");
+
+        assert!(matches!(engine.eval_str("import db\ndb.exec(1)"), Err(Error::Compile(_))));
+        assert!(matches!(engine.eval_str("import db\ndb.query()"), Err(Error::Compile(_))));
+        assert!(matches!(engine.eval_str("conn.query()"), Err(Error::Runtime(_))));
+    }
+
+    #[test]
+    fn sys_module() {
+        let mut engine = Engine::new();
+        engine.set_args(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(engine.eval_str("import sys\nsys.args").unwrap().__repr__(), "[\"a\", \"b\"]");
+        assert!(matches!(engine.eval_str("sys.env(\"NIGHTJAR_SURELY_UNSET\")"), Ok(Value::None)));
+
+        let err = engine.eval_str("sys.exit(3)\nprint(1)").unwrap_err();
+        assert_eq!(err.exit_status(), Some(3));
+        assert_eq!(Error::Runtime(RuntimeError::zero_division()).exit_status(), None);
+        for code in ["256", "300", "-1"] {
+            let err = engine.eval_str(&format!("sys.exit({})", code)).unwrap_err();
+            assert!(matches!(err, Error::Runtime(RuntimeError { kind: ErrorKind::Value, .. })));
+        }
+        assert_eq!(engine.eval_str("sys.exit(255)").unwrap_err().exit_status(), Some(255));
+    }
+
+    #[test]
+    fn conditions_use_truthiness() {
+        let mut engine = Engine::new();
+        engine.eval_str("fun truthy(x: int): int {\n    if x {\n        return 1\n    }\n    return 0\n}").unwrap();
+        for value in ["0", "7", "0.0", "0.5", "\"\"", "\"a\"", "none", "[]", "[0]", "{}", "(1, 2)", "9223372036854775807 + 1"] {
+            let truthy = engine.eval_str(&format!("truthy({})", value)).unwrap();
+            let expected = engine.eval_str(&format!("bool({})", value)).unwrap().as_bool();
+            assert_eq!(truthy.as_bool(), expected, "if {}", value);
+        }
+    }
+
+    #[test]
+    fn failed_import_runs_again() {
+        let dir = std::env::temp_dir().join(format!("nightjar_import_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("failing.njar"), "pub let ready = 1 / 0").unwrap();
+
+        let mut engine = Engine::with_search_path(vec![dir.clone()]);
+        for _ in 0..2 {
+            match engine.eval_str("import failing\nprint(failing.ready)") {
+                Err(Error::Runtime(err)) => assert_eq!(err.kind, ErrorKind::ZeroDivision),
+                result => panic!("expected the module body to fail, got {:?}", result),
+            }
+        }
+        // A caught failure also leaves the module to be imported again
+        engine.eval_str("try {\n    import failing\n} catch {\n}").unwrap();
+        assert!(engine.eval_str("import failing").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_input() {
+        let mut engine = Engine::new();
+        engine.set_input(std::io::Cursor::new("first\r\n\nrest\nof it"));
+        engine.eval_str("let a = read_line()\nlet b = read_line()\nlet c = read_all()\nlet d = read_line()").unwrap();
+        assert_eq!(engine.get_global("a").unwrap().__str__(), "first");
+        assert_eq!(engine.get_global("b").unwrap().__str__(), "");
+        assert_eq!(engine.get_global("c").unwrap().__str__(), "rest\nof it");
+        assert!(matches!(engine.get_global("d"), Some(Value::None)));
+    }
+}
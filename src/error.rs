@@ -0,0 +1,167 @@
+use std::fmt;
+
+use crate::parser::ParseError;
+use crate::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    ZeroDivision,
+    Type,
+    Value,
+    Name,
+    Index,
+    Key,
+    Assertion,
+    IO,
+    /// Calls nested deeper than the VM allows
+    Recursion,
+    /// `throw` of a value which is not an `Error` struct
+    Thrown,
+    /// Raised by `sys.exit`, unwinds the script without running `catch` or `finally`
+    /// blocks and carries the exit status to the host
+    Exit(i32),
+}
+
+impl ErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::ZeroDivision => "ZeroDivisionError",
+            ErrorKind::Type => "TypeError",
+            ErrorKind::Value => "ValueError",
+            ErrorKind::Name => "NameError",
+            ErrorKind::Index => "IndexError",
+            ErrorKind::Key => "KeyError",
+            ErrorKind::Assertion => "AssertionError",
+            ErrorKind::IO => "IOError",
+            ErrorKind::Recursion => "RecursionError",
+            ErrorKind::Thrown => "Error",
+            ErrorKind::Exit(_) => "SystemExit",
+        }
+    }
+
+    /// Kind of a rethrown error, scripts cannot raise `SystemExit` this way
+    pub fn from_name(name: &str) -> Option<Self> {
+        let kinds = [
+            ErrorKind::ZeroDivision, ErrorKind::Type, ErrorKind::Value, ErrorKind::Name, ErrorKind::Index,
+            ErrorKind::Key, ErrorKind::Assertion, ErrorKind::IO, ErrorKind::Recursion, ErrorKind::Thrown,
+        ];
+        kinds.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// Errors a `catch` block can handle, every kind but `Exit`, which skips `finally` blocks too
+    pub fn is_catchable(&self) -> bool {
+        !matches!(self, ErrorKind::Exit(_))
+    }
+}
+
+/// Error raised while executing bytecode
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Value given to `throw`, `catch` receives it unchanged
+    pub value: Option<Value>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        RuntimeError { kind, message: message.into(), value: None }
+    }
+
+    pub fn zero_division() -> Self {
+        RuntimeError::new(ErrorKind::ZeroDivision, "division by zero")
+    }
+
+    pub fn io(err: std::io::Error) -> Self {
+        RuntimeError::new(ErrorKind::IO, err.to_string())
+    }
+
+    /// I/O failure on a file, the message names it
+    pub fn io_at(path: &str, err: std::io::Error) -> Self {
+        RuntimeError::new(ErrorKind::IO, format!("{}: {}", path, err))
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.name(), self.message)
+    }
+}
+
+/// Error found before execution, while resolving or compiling modules
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(message: impl Into<String>) -> Self {
+        CompileError { message: message.into() }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CompileError: {}", self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl std::error::Error for CompileError {}
+
+/// Any failure reported by `Engine`
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Parse(ParseError),
+    Compile(CompileError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "IOError: {}", err),
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::Compile(err) => write!(f, "{}", err),
+            Error::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error {
+    /// Status requested by `sys.exit`
+    pub fn exit_status(&self) -> Option<i32> {
+        match self {
+            Error::Runtime(RuntimeError { kind: ErrorKind::Exit(code), .. }) => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl From<CompileError> for Error {
+    fn from(err: CompileError) -> Self {
+        Error::Compile(err)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Self {
+        Error::Runtime(err)
+    }
+}
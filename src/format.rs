@@ -0,0 +1,81 @@
+/// Bracket depth tracking shared by the formatter and the REPL,
+/// brackets inside strings and comments are not counted
+#[derive(Default)]
+pub struct Brackets {
+    pub depth: i32,
+    pub in_string: bool,
+}
+
+impl Brackets {
+    pub fn scan(&mut self, text: &str) {
+        let mut in_comment = false;
+        for c in text.chars() {
+            match c {
+                '\n' => in_comment = false,
+                _ if in_comment => {}
+                '"' => self.in_string = !self.in_string,
+                _ if self.in_string => {}
+                '#' => in_comment = true,
+                '{' | '(' | '[' => self.depth += 1,
+                '}' | ')' | ']' => self.depth -= 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Reindents code by bracket depth with four spaces, strips trailing whitespace
+/// and collapses runs of blank lines to at most two, lines inside multi-line strings are kept as is.
+/// Sources using CRLF line endings keep them
+pub fn format_source(source: &str) -> String {
+    let newline = if source.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut out = String::new();
+    let mut brackets = Brackets::default();
+    let mut pending_blanks = 0;
+
+    for line in source.lines() {
+        if brackets.in_string {
+            out.push_str(line);
+            out.push_str(newline);
+            brackets.scan(line);
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            if !out.is_empty() {
+                pending_blanks = (pending_blanks + 1).min(2);
+            }
+            continue;
+        }
+        for _ in 0..pending_blanks {
+            out.push_str(newline);
+        }
+        pending_blanks = 0;
+
+        let closing = trimmed.chars().take_while(|c| matches!(c, '}' | ')' | ']')).count() as i32;
+        let indent = (brackets.depth - closing).max(0) as usize;
+        out.push_str(&"    ".repeat(indent));
+        out.push_str(trimmed);
+        out.push_str(newline);
+        brackets.scan(line);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reindent() {
+        let source = "\n\nfun f(a: int): int {\n  if a > 1 {   \nreturn a # {\n  } else {\n\n\n\n        return \"}\n  x\"\n}\n}\n\n";
+        let expected = "fun f(a: int): int {\n    if a > 1 {\n        return a # {\n    } else {\n\n\n        return \"}\n  x\"\n    }\n}\n";
+        assert_eq!(format_source(source), expected);
+        assert_eq!(format_source(expected), expected);
+
+        let crlf = expected.replace('\n', "\r\n");
+        assert_eq!(format_source(&source.replace('\n', "\r\n")), crlf);
+        assert_eq!(format_source(&crlf), crlf);
+    }
+}
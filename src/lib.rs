@@ -0,0 +1,25 @@
+//! Nightjar programming language, `Engine` is the entry point for embedding
+
+pub mod ast;
+pub mod bigint;
+pub mod code_obj;
+pub mod convert;
+pub mod error;
+pub mod parser;
+pub mod value;
+
+mod builtins;
+mod compiler;
+mod engine;
+mod loader;
+mod native;
+mod opcode;
+mod vm;
+
+pub use convert::{FromValue, IntoValue};
+pub use engine::{Engine, MAIN_MODULE};
+pub use error::Error;
+pub use loader::SEARCH_PATH_VAR;
+pub use native::{NativeFn, NativeModule, Signature};
+pub use value::Value;
+pub use vm::VM;
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast;
+use crate::code_obj::CodeObject;
+use crate::compiler;
+use crate::error::CompileError;
+use crate::native::Natives;
+use crate::parser;
+
+pub const SEARCH_PATH_VAR: &str = "NIGHTJAR_PATH";
+
+/// Finds and compiles imported modules, each file is compiled once per run
+pub struct Loader {
+    search_path: Vec<PathBuf>,
+    cache: HashMap<PathBuf, Rc<CodeObject>>,
+    /// Modules being compiled right now, the innermost import is last
+    loading: Vec<PathBuf>,
+}
+
+impl Loader {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Loader { search_path, cache: HashMap::new(), loading: vec![] }
+    }
+
+    /// Search path taken from `NIGHTJAR_PATH`, formatted like `PATH`
+    pub fn from_env() -> Self {
+        let search_path = match std::env::var_os(SEARCH_PATH_VAR) {
+            Some(paths) => std::env::split_paths(&paths).collect(),
+            None => vec![],
+        };
+        Loader::new(search_path)
+    }
+
+    /// Compiles already parsed module located at `path`
+    pub fn compile(&mut self, path: &Path, module: &ast::Module, natives: &Natives) -> Result<Rc<CodeObject>, CompileError> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(co) = self.cache.get(&path) {
+            return Ok(co.clone());
+        }
+
+        self.loading.push(path.clone());
+        let co = compiler::compile_module(module, self, natives, &path);
+        self.loading.pop();
+
+        let co = Rc::new(co?);
+        self.cache.insert(path, co.clone());
+        Ok(co)
+    }
+
+    /// Compiles code running inside an existing module, see `compiler::compile_into`,
+    /// the result is not cached as the same module can get more code later
+    pub fn compile_into(
+        &mut self,
+        path: &Path,
+        module: &ast::Module,
+        natives: &Natives,
+        name: &str,
+        names: &[String],
+    ) -> Result<Rc<CodeObject>, CompileError> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.loading.push(path.clone());
+        let co = compiler::compile_into(module, self, natives, &path, name, names);
+        self.loading.pop();
+        Ok(Rc::new(co?))
+    }
+
+    /// Resolves `import a.b` inside `importer` to `a/b.njar`, looking first next to
+    /// the importing file and then in the search path
+    pub fn load(&mut self, name: &str, importer: &Path, natives: &Natives) -> Result<Rc<CodeObject>, CompileError> {
+        let path = self.resolve(name, importer)
+            .ok_or_else(|| CompileError::new(format!("module {} not found", name)))?;
+
+        if let Some(start) = self.loading.iter().position(|p| *p == path) {
+            let mut cycle: Vec<String> = self.loading[start..].iter().map(|p| p.display().to_string()).collect();
+            cycle.push(path.display().to_string());
+            return Err(CompileError::new(format!("circular import: {}", cycle.join(" -> "))));
+        }
+        if let Some(co) = self.cache.get(&path) {
+            return Ok(co.clone());
+        }
+
+        let source = fs::read_to_string(&path)
+            .map_err(|e| CompileError::new(format!("cannot read module {}: {}", path.display(), e)))?;
+        let module = parser::parse_named_source(&source, &path.display().to_string())
+            .map_err(|e| CompileError::new(e.to_string()))?;
+        self.compile(&path, &module, natives)
+    }
+
+    fn resolve(&self, name: &str, importer: &Path) -> Option<PathBuf> {
+        let relative: PathBuf = name.split('.').collect::<PathBuf>().with_extension("njar");
+        let importer_dir = importer.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        std::iter::once(importer_dir)
+            .chain(self.search_path.iter().cloned())
+            .map(|dir| dir.join(&relative))
+            .find(|candidate| candidate.is_file())
+            .and_then(|found| found.canonicalize().ok())
+    }
+}
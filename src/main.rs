@@ -1,37 +1,194 @@
-mod vm;
-mod parser;
-mod compiler;
-mod code_obj;
-mod value;
-mod opcode;
-pub mod ast;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::rc::Rc;
 
+use nightjar::code_obj::{self, CodeObject};
+use nightjar::{ast, parser, Engine, Error, Value};
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() == 1 {
-        println!("Nightjar programming language, usage: nightjar <path>")
-    } else {
-        let file_path = &args[1];
-        let ast_verbose = args.contains(&"--ast".to_string());
-        let dis_verbose = args.contains(&"--dis".to_string());
-        let vm_verbose = args.contains(&"--vm".to_string());
-
-        let ast = parser::parse_file(file_path);
-        if ast_verbose {
-            ast::pretty_print(&ast);
-        }
-
-        let co = compiler::compile_module(&ast);
-        if dis_verbose {
-            code_obj::dis(&co);
-        }
-        
-        let mut vm = vm::VM::new();
-        vm.run(co);
-        if vm_verbose {
-            println!("VM final state");
-            vm.print_state();
+use cli::{Command, Source};
+
+mod cli;
+mod format;
+mod repl;
+
+/// Failing tests or unformatted files
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 64;
+/// Script does not parse or compile
+const EXIT_DATA_ERROR: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+/// Uncaught runtime error
+const EXIT_RUNTIME_ERROR: i32 = 70;
+
+
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::Io(_) => EXIT_NO_INPUT,
+        Error::Parse(_) | Error::Compile(_) => EXIT_DATA_ERROR,
+        Error::Runtime(_) => EXIT_RUNTIME_ERROR,
+    }
+}
+
+/// Prints the error and turns it into an exit code, `sys.exit` is not an error
+fn report(result: Result<(), Error>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(err) => match err.exit_status() {
+            Some(code) => code,
+            None => {
+                eprintln!("{}", err);
+                exit_code(&err)
+            }
+        },
+    }
+}
+
+/// Source text with the path used for error messages and resolving imports
+fn read_source(source: &Source) -> Result<(String, PathBuf), Error> {
+    match source {
+        Source::File(path) => Ok((fs::read_to_string(path)?, path.clone())),
+        Source::Stdin => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            Ok((text, PathBuf::from("<stdin>")))
         }
+        Source::Inline(code) => Ok((code.clone(), PathBuf::from("<string>"))),
     }
 }
+
+fn parse(source: &Source) -> Result<(ast::Module, PathBuf), Error> {
+    let (text, path) = read_source(source)?;
+    let module = parser::parse_named_source(&text, &path.display().to_string())?;
+    Ok((module, path))
+}
+
+fn compile(engine: &mut Engine, source: &Source) -> Result<Rc<CodeObject>, Error> {
+    let (module, path) = parse(source)?;
+    engine.compile(&path, &module)
+}
+
+fn run(source: &Source, args: Vec<String>, vm_verbose: bool) -> Result<(), Error> {
+    let mut engine = Engine::new();
+    engine.set_args(args);
+    let co = compile(&mut engine, source)?;
+    engine.run(co)?;
+    if vm_verbose {
+        println!("VM final state");
+        engine.print_state();
+    }
+    Ok(())
+}
+
+/// Every source is checked, the first failure decides the exit code
+fn check(sources: &[Source]) -> i32 {
+    sources.iter()
+        .map(|source| report(compile(&mut Engine::new(), source).map(|_| ())))
+        .fold(0, |code, result| if code == 0 { result } else { code })
+}
+
+/// Files are rewritten in place, stdin is formatted to stdout
+fn fmt(sources: &[Source], check: bool) -> Result<i32, Error> {
+    let mut code = 0;
+    for source in sources {
+        let (text, path) = read_source(source)?;
+        let formatted = format::format_source(&text);
+        match source {
+            _ if check => {
+                if formatted != text {
+                    println!("{} is not formatted", path.display());
+                    code = EXIT_FAILURE;
+                }
+            }
+            Source::File(path) if formatted != text => fs::write(path, formatted)?,
+            Source::File(_) => {}
+            _ => print!("{}", formatted),
+        }
+    }
+    Ok(code)
+}
+
+/// Test files are named `test_*.njar` or `*_test.njar`, paths given explicitly are always run
+fn test_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut found: Vec<PathBuf> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| {
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                name.ends_with(".njar") && (name.starts_with("test_") || name.ends_with("_test.njar"))
+            })
+            .collect();
+        found.sort();
+        files.extend(found);
+    }
+    Ok(files)
+}
+
+/// Every file runs in its own engine, then each of its `test_*` functions is called
+fn test(paths: &[PathBuf]) -> Result<i32, Error> {
+    let (mut passed, mut failed) = (0, 0);
+    for file in test_files(paths)? {
+        let mut engine = Engine::new();
+        if let Err(err) = engine.eval_file(&file) {
+            println!("{} ... FAILED\n    {}", file.display(), err);
+            failed += 1;
+            continue;
+        }
+        let tests: Vec<String> = engine.globals().into_iter()
+            .filter(|(name, value)| name.starts_with("test_") && matches!(value, Value::Function(_)))
+            .map(|(name, _)| name)
+            .collect();
+        for name in tests {
+            match engine.call_function(&name, vec![]) {
+                Ok(_) => {
+                    println!("{}::{} ... ok", file.display(), name);
+                    passed += 1;
+                }
+                Err(err) => {
+                    println!("{}::{} ... FAILED\n    {}", file.display(), name, err);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!("\n{} passed, {} failed", passed, failed);
+    Ok(if failed > 0 { EXIT_FAILURE } else { 0 })
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match cli::parse_args(&args) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, cli::USAGE);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+
+    let code = match command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            0
+        }
+        Command::Version => {
+            println!("nightjar {}", env!("CARGO_PKG_VERSION"));
+            0
+        }
+        Command::Repl => {
+            repl::run();
+            0
+        }
+        Command::Run { source, args, vm_verbose } => report(run(&source, args, vm_verbose)),
+        Command::Check { sources } => check(&sources),
+        Command::Dis { source } => report(compile(&mut Engine::new(), &source).map(|co| code_obj::dis(&co))),
+        Command::Ast { source } => report(parse(&source).map(|(module, _)| ast::pretty_print(&module))),
+        Command::Fmt { sources, check } => fmt(&sources, check).unwrap_or_else(|err| report(Err(err))),
+        Command::Test { paths } => test(&paths).unwrap_or_else(|err| report(Err(err))),
+    };
+    std::process::exit(code);
+}
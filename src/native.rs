@@ -0,0 +1,161 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::RuntimeError;
+use crate::value::{Module, Value};
+use crate::vm::VM;
+
+/// Host function callable from scripts, arguments are already checked against its signature
+pub type NativeFn = fn(&mut VM, &[Value]) -> Result<Value, RuntimeError>;
+
+/// Arguments a native accepts, a plain number is a fixed arity. Optional
+/// and keyword arguments the caller leaves out are passed as none, keywords
+/// come after the positional ones in declaration order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub arity: usize,
+    /// Positional arguments after the required ones, not used with `variadic`
+    pub optional: usize,
+    /// Any number of positional arguments from `arity` on
+    pub variadic: bool,
+    pub keywords: Vec<String>,
+}
+
+impl Signature {
+    pub fn fixed(arity: usize) -> Self {
+        Signature { arity, optional: 0, variadic: false, keywords: vec![] }
+    }
+
+    pub fn variadic(min: usize) -> Self {
+        Signature { arity: min, optional: 0, variadic: true, keywords: vec![] }
+    }
+
+    pub fn optional(mut self, count: usize) -> Self {
+        self.optional = count;
+        self
+    }
+
+    pub fn keywords(mut self, names: &[&str]) -> Self {
+        self.keywords = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Checks the number of positional arguments, the message names the function
+    pub fn check(&self, name: &str, argc: usize) -> Result<(), String> {
+        let max = self.arity + self.optional;
+        match (self.variadic, argc) {
+            (true, argc) if argc < self.arity => {
+                Err(format!("{} takes at least {} arguments but {} were given", name, self.arity, argc))
+            }
+            (false, argc) if self.optional == 0 && argc != self.arity => {
+                Err(format!("{} takes {} arguments but {} were given", name, self.arity, argc))
+            }
+            (false, argc) if argc < self.arity || argc > max => {
+                Err(format!("{} takes {} to {} arguments but {} were given", name, self.arity, max, argc))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Optional arguments the caller left out, padded with none
+    pub fn missing(&self, argc: usize) -> usize {
+        (self.arity + self.optional).saturating_sub(argc)
+    }
+}
+
+impl From<usize> for Signature {
+    fn from(arity: usize) -> Self {
+        Signature::fixed(arity)
+    }
+}
+
+#[derive(Debug)]
+pub struct Native {
+    pub name: String,
+    pub signature: Signature,
+    pub function: NativeFn,
+}
+
+/// Namespace of host functions and constants which scripts can `import`
+pub struct NativeModule {
+    name: String,
+    functions: Vec<(String, Signature, NativeFn)>,
+    constants: Vec<(String, Value)>,
+}
+
+impl NativeModule {
+    pub fn new(name: &str) -> Self {
+        NativeModule { name: name.to_string(), functions: vec![], constants: vec![] }
+    }
+
+    pub fn function(mut self, name: &str, signature: impl Into<Signature>, function: NativeFn) -> Self {
+        self.functions.push((name.to_string(), signature.into(), function));
+        self
+    }
+
+    pub fn constant(mut self, name: &str, value: Value) -> Self {
+        self.constants.push((name.to_string(), value));
+        self
+    }
+}
+
+/// Registered natives, calls to them are resolved to an index at compile
+/// time and run through `call_native`
+#[derive(Default)]
+pub struct Natives {
+    functions: Vec<Rc<Native>>,
+    /// Native modules as they are bound by `import`, all members are public
+    modules: Vec<Rc<Module>>,
+}
+
+impl Natives {
+    pub fn new() -> Self {
+        Natives::default()
+    }
+
+    /// Registering a name again replaces the previous function,
+    /// code compiled before keeps calling the same index
+    pub fn register(&mut self, name: &str, signature: impl Into<Signature>, function: NativeFn) -> Rc<Native> {
+        let native = Rc::new(Native { name: name.to_string(), signature: signature.into(), function });
+        match self.functions.iter().position(|n| n.name == name) {
+            Some(pos) => self.functions[pos] = native.clone(),
+            None => self.functions.push(native.clone()),
+        }
+        native
+    }
+
+    /// Module functions are registered as `module.function`
+    pub fn register_module(&mut self, module: NativeModule) {
+        let mut members = vec![];
+        for (name, signature, function) in module.functions {
+            let native = self.register(&format!("{}.{}", module.name, name), signature, function);
+            members.push((name, Value::Native(native)));
+        }
+        members.extend(module.constants);
+
+        let (names, globals): (Vec<String>, Vec<Value>) = members.into_iter().unzip();
+        let module = Rc::new(Module {
+            name: module.name,
+            exports: names.clone(),
+            names: RefCell::new(names),
+            globals: RefCell::new(globals),
+        });
+        match self.modules.iter().position(|m| m.name == module.name) {
+            Some(pos) => self.modules[pos] = module,
+            None => self.modules.push(module),
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<(u16, &Native)> {
+        let pos = self.functions.iter().position(|n| n.name == name)?;
+        Some((pos as u16, &self.functions[pos]))
+    }
+
+    pub fn module(&self, name: &str) -> Option<&Rc<Module>> {
+        self.modules.iter().find(|m| m.name == name)
+    }
+
+    pub fn get(&self, index: usize) -> &Native {
+        &self.functions[index]
+    }
+}
@@ -1,29 +1,71 @@
 #[repr(u8)]
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum OpCode {
     // Stack Manipulation
     push_const,
     push_var,
+    push_local,
     pop,
     copy,
+    /// Duplicates the two values on top of the stack, keeping their order
+    copy2,
 
     store_var,
+    store_local,
+
+    // Strings
+    format_value,
+    build_string,
+
+    // Collections
+    build_tuple,
+    build_list,
+    build_map,
+    build_struct,
+    unpack,
+    get_item,
+    set_item,
+    get_iter,
+    for_iter,
+
+    // Functions
+    make_function,
+    call,
+    call_method,
+    call_native,
+    ret,
+
+    // Modules
+    import_module,
+    get_attr,
+    set_attr,
 
-    // Debug
-    print,
-    
     // Control flow
     jmp,
-    jmp_nonzero,
     pop_jmp_ifzero,
+    /// Replaces an `Ok` result with its value and jumps, an `Err` one is kept
+    /// with the stack cut down to a depth above the locals
+    unwrap_ok,
     halt,
+    /// Raises the value on top of the stack, see `VM::unwind`
+    throw,
 
     // Math operations
     add,
     sub,
     mul,
     div,
+    floor_div,
+    rem,
+    pow,
+    neg,
+    lshift,
+    rshift,
+    bit_and,
+    bit_or,
+    bit_xor,
+    bit_not,
 
     // Compare
     gt, // >
@@ -39,4 +81,16 @@ impl OpCode {
     pub fn from_u8(val: u8) -> Self {
         unsafe { std::mem::transmute(val)}
     }
+
+    /// Number of u16 operands following the opcode in bytecode
+    pub fn operand_count(&self) -> usize {
+        use OpCode::*;
+        match self {
+            call_method | call_native | unwrap_ok => 2,
+            push_const | push_var | push_local | store_var | store_local
+            | format_value | build_string | build_tuple | build_list | build_map | build_struct | unpack
+            | for_iter | call | import_module | get_attr | set_attr | jmp | pop_jmp_ifzero => 1,
+            _ => 0,
+        }
+    }
 }
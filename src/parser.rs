@@ -1,6 +1,8 @@
 use std::fs;
+use std::iter::Peekable;
 
-use pest::{Parser, iterators::Pair};
+use pest::{Parser, Span, iterators::{Pair, Pairs}};
+use pest::error::{Error, ErrorVariant};
 use pest::pratt_parser::PrattParser;
 use pest_derive::Parser;
 
@@ -10,6 +12,13 @@ use crate::ast;
 #[grammar = "grammar/njar.pest"]
 pub struct Grammar;
 
+pub type ParseError = Box<Error<Rule>>;
+type ParseResult<T> = Result<T, ParseError>;
+
+fn error_at(span: Span, message: String) -> ParseError {
+    Box::new(Error::new_from_span(ErrorVariant::CustomError { message }, span))
+}
+
 
 lazy_static::lazy_static! {
     static ref PRATT_PARSER: PrattParser<Rule> = {
@@ -25,24 +34,30 @@ lazy_static::lazy_static! {
             .op(Op::infix(bit_and, Left))
             .op(Op::infix(lshift, Left) | Op::infix(rshift, Left))
             .op(Op::infix(add, Left) | Op::infix(sub, Left))
-            .op(Op::infix(mul, Left) | Op::infix(div, Left) | Op::infix(matmul, Left) | Op::infix(rem, Left))
+            .op(Op::infix(mul, Left) | Op::infix(div, Left) | Op::infix(floor_div, Left) | Op::infix(matmul, Left) | Op::infix(rem, Left))
             .op(Op::prefix(negative) | Op::prefix(bit_not))
             .op(Op::infix(exp, Left))
+            .op(Op::postfix(index) | Op::postfix(try_op))
     };
 }
 
 
-fn parse_expr(pair: Pair<Rule>) -> ast::Expr {
+fn parse_expr(pair: Pair<Rule>) -> ParseResult<ast::Expr> {
     assert_eq!(Rule::expr, pair.as_rule());
     let pairs = pair.into_inner();
     PRATT_PARSER
         .map_primary(|primary| match primary.as_rule() {
             Rule::fun_call => parse_fun_call(primary),
-            Rule::type_init => unimplemented!(),
-            Rule::var_call => ast::Expr::VarCall{name: primary.as_str().to_string()},
-            Rule::integer => ast::Expr::Integer(primary.as_str().trim().parse().unwrap()),
-            Rule::float => unimplemented!(),
-            Rule::string => ast::Expr::String(primary.as_str().strip_prefix("\"").unwrap().strip_suffix("\"").unwrap().to_string()),
+            Rule::type_init => parse_type_init(primary),
+            Rule::none => Ok(ast::Expr::None),
+            Rule::var_call => Ok(ast::Expr::VarCall{name: primary.as_str().to_string()}),
+            Rule::integer => parse_integer(primary),
+            Rule::float => Ok(ast::Expr::Float(primary.as_str().replace('_', "").parse().unwrap())),
+            Rule::string => Ok(ast::Expr::String(primary.as_str().strip_prefix("\"").unwrap().strip_suffix("\"").unwrap().to_string())),
+            Rule::fstring => parse_fstring(primary),
+            Rule::tuple => Ok(ast::Expr::Tuple(parse_exprs(primary)?)),
+            Rule::list => Ok(ast::Expr::List(parse_exprs(primary)?)),
+            Rule::map => parse_map(primary),
             Rule::expr => parse_expr(primary),
             rule => unreachable!("Expr::parse expected atom, found {:?}", rule)
         })
@@ -52,6 +67,7 @@ fn parse_expr(pair: Pair<Rule>) -> ast::Expr {
                 Rule::mul => ast::BinOp::Mul,
                 Rule::matmul => ast::BinOp::MatMul,
                 Rule::div => ast::BinOp::Div,
+                Rule::floor_div => ast::BinOp::FloorDiv,
                 Rule::rem => ast::BinOp::Rem,
                 Rule::add => ast::BinOp::Add,
                 Rule::sub => ast::BinOp::Sub,
@@ -72,52 +88,163 @@ fn parse_expr(pair: Pair<Rule>) -> ast::Expr {
                 Rule::or => ast::BinOp::Or,
                 rule => unreachable!("Expr::parse expected infix operation, found {:?}", rule),
             };
-            ast::Expr::Binary {
-                lhs: Box::new(lhs),
+            Ok(ast::Expr::Binary {
+                lhs: Box::new(lhs?),
                 op,
-                rhs: Box::new(rhs),
-            }
+                rhs: Box::new(rhs?),
+            })
         })
         .map_prefix(|op, rhs| match op.as_rule() {
-            Rule::negative => ast::Expr::Unary{ op: ast::UnaryOp::Negative, expr: Box::new(rhs) },
-            Rule::bit_not => ast::Expr::Unary{ op: ast::UnaryOp::BitNot, expr: Box::new(rhs)},
-            Rule::not => ast::Expr::Unary{op: ast::UnaryOp::Not, expr: Box::new(rhs)},
+            Rule::negative => Ok(ast::Expr::Unary{ op: ast::UnaryOp::Negative, expr: Box::new(rhs?) }),
+            Rule::bit_not => Ok(ast::Expr::Unary{ op: ast::UnaryOp::BitNot, expr: Box::new(rhs?)}),
+            Rule::not => Ok(ast::Expr::Unary{op: ast::UnaryOp::Not, expr: Box::new(rhs?)}),
+            _ => unreachable!(),
+        })
+        .map_postfix(|lhs, op| match op.as_rule() {
+            Rule::index => Ok(ast::Expr::Index { base: Box::new(lhs?), index: Box::new(parse_expr(op.into_inner().next().unwrap())?) }),
+            Rule::try_op => Ok(ast::Expr::Try(Box::new(lhs?))),
             _ => unreachable!(),
         })
         .parse(pairs)
 }
 
 
-fn parse_fun_call(pair: Pair<Rule>) -> ast::Expr {
+/// Accepts decimal, `0x`, `0o` and `0b` forms with `_` digit separators
+fn parse_integer(pair: Pair<Rule>) -> ParseResult<ast::Expr> {
+    assert_eq!(pair.as_rule(), Rule::integer);
+
+    let literal = pair.as_str().replace('_', "");
+    let (digits, radix) = match literal.get(..2).map(|prefix| prefix.to_ascii_lowercase()).as_deref() {
+        Some("0x") => (&literal[2..], 16),
+        Some("0o") => (&literal[2..], 8),
+        Some("0b") => (&literal[2..], 2),
+        _ => (&literal[..], 10),
+    };
+
+    match i64::from_str_radix(digits, radix) {
+        Ok(value) => Ok(ast::Expr::Integer(value)),
+        Err(_) => Err(error_at(pair.as_span(), format!("integer literal {} is out of range", pair.as_str()))),
+    }
+}
+
+fn parse_fstring(pair: Pair<Rule>) -> ParseResult<ast::Expr> {
+    assert_eq!(pair.as_rule(), Rule::fstring);
+
+    let parts = pair.into_inner().map(|part| match part.as_rule() {
+        Rule::fstring_text => {
+            let text = part.as_str().replace("{{", "{").replace("}}", "}");
+            Ok(ast::FStringPart::Literal(text))
+        }
+        Rule::fstring_field => {
+            let mut rules = part.into_inner();
+            let expr = parse_expr(rules.next().unwrap())?;
+            let spec = rules.next().map(|spec| spec.as_str().to_string());
+            assert_eq!(rules.next(), None);
+            Ok(ast::FStringPart::Field{expr, spec})
+        }
+        rule => unreachable!("FString::parse expected text or field, found {:?}", rule),
+    });
+
+    Ok(ast::Expr::FString(parts.collect::<ParseResult<_>>()?))
+}
+
+
+fn parse_exprs(pair: Pair<Rule>) -> ParseResult<Vec<ast::Expr>> {
+    pair.into_inner().map(parse_expr).collect()
+}
+
+fn parse_map(pair: Pair<Rule>) -> ParseResult<ast::Expr> {
+    assert_eq!(pair.as_rule(), Rule::map);
+
+    let entries = pair.into_inner().map(|entry| {
+        let mut rules = entry.into_inner();
+        let key = parse_expr(rules.next().unwrap())?;
+        let value = parse_expr(rules.next().unwrap())?;
+        Ok((key, value))
+    });
+
+    Ok(ast::Expr::Map(entries.collect::<ParseResult<_>>()?))
+}
+
+
+fn parse_type_init(pair: Pair<Rule>) -> ParseResult<ast::Expr> {
+    assert_eq!(pair.as_rule(), Rule::type_init);
+    let mut rules = pair.into_inner();
+    let name = rules.next().unwrap().as_str().to_string();
+    let fields = rules.next().unwrap().into_inner().map(|field_arg| {
+        let mut rules = field_arg.into_inner();
+        let field = rules.next().unwrap().as_str().to_string();
+        Ok((field, parse_expr(rules.next().unwrap())?))
+    });
+    let fields = fields.collect::<ParseResult<_>>()?;
+    Ok(ast::Expr::StructInit{name, fields})
+}
+
+fn parse_fun_call(pair: Pair<Rule>) -> ParseResult<ast::Expr> {
     assert_eq!(pair.as_rule(), Rule::fun_call);
 
     let mut rules = pair.into_inner();
     let ident = rules.next().unwrap();
     let arglist = rules.next().unwrap();
 
-    ast::Expr::FunCall {
+    let (args, kwargs) = parse_arglist(arglist)?;
+    Ok(ast::Expr::FunCall {
         name: ident.as_str().to_string(),
-        args: parse_arglist(arglist)
-    }
+        args,
+        kwargs,
+    })
 }
 
-fn parse_arglist(pair: Pair<Rule>) -> Vec<ast::Expr> {
+/// Positional arguments and `name=value` keyword arguments
+type Args = (Vec<ast::Expr>, Vec<(String, ast::Expr)>);
+
+/// Keyword arguments have to come after the positional ones
+fn parse_arglist(pair: Pair<Rule>) -> ParseResult<Args> {
     assert_eq!(pair.as_rule(), Rule::arg_list);
-    pair.into_inner().map(parse_expr).collect()
+    let mut args = vec![];
+    let mut kwargs: Vec<(String, ast::Expr)> = vec![];
+    for arg in pair.into_inner() {
+        if arg.as_rule() != Rule::keyword_arg {
+            if !kwargs.is_empty() {
+                return Err(error_at(arg.as_span(), "positional argument follows keyword argument".to_string()));
+            }
+            args.push(parse_expr(arg)?);
+            continue;
+        }
+        let span = arg.as_span();
+        let mut rules = arg.into_inner();
+        let name = rules.next().unwrap().as_str().to_string();
+        if kwargs.iter().any(|(other, _)| *other == name) {
+            return Err(error_at(span, format!("keyword argument '{}' repeated", name)));
+        }
+        kwargs.push((name, parse_expr(rules.next().unwrap())?));
+    }
+    Ok((args, kwargs))
 }
 
 
-fn parse_stmt(pair: Pair<Rule>) -> ast::Stmt {
+fn parse_stmt(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
     match pair.as_rule() {
-        Rule::expr => ast::Stmt::Expr(parse_expr(pair)),
+        Rule::expr => Ok(ast::Stmt::Expr(parse_expr(pair)?)),
+        Rule::import_stmt => parse_import_stmt(pair),
         Rule::fun_decl => parse_fun_decl(pair),
         Rule::var_decl => parse_var_decl(pair),
+        Rule::assign_stmt => parse_assign_stmt(pair),
+        Rule::return_stmt => parse_return_stmt(pair),
         Rule::if_stmt => parse_if_stmt(pair),
+        Rule::while_loop => parse_while_loop(pair),
+        Rule::for_loop => parse_for_loop(pair),
+        Rule::break_stmt => Ok(ast::Stmt::Break(parse_label(&mut pair.into_inner().peekable()))),
+        Rule::continue_stmt => Ok(ast::Stmt::Continue(parse_label(&mut pair.into_inner().peekable()))),
+        Rule::throw_stmt => Ok(ast::Stmt::Throw(parse_expr(pair.into_inner().next().unwrap())?)),
+        Rule::try_stmt => parse_try_stmt(pair),
+        Rule::struct_decl => parse_struct_decl(pair),
+        Rule::enum_decl => parse_enum_decl(pair),
         _ => unimplemented!(),
     }
 }
 
-fn parse_if_stmt(pair: Pair<Rule>) -> ast::Stmt {
+fn parse_if_stmt(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
     assert_eq!(pair.as_rule(), Rule::if_stmt);
     let mut rules = pair.into_inner();
     let test = rules.next().unwrap();
@@ -125,67 +252,303 @@ fn parse_if_stmt(pair: Pair<Rule>) -> ast::Stmt {
 
     let else_body = if let Some(else_stmt) = rules.next() {
         assert_eq!(else_stmt.as_rule(), Rule::else_stmt);
-        Some(parse_block(else_stmt.into_inner().next().unwrap()))
+        Some(parse_block(else_stmt.into_inner().next().unwrap())?)
     } else {
         None
     };
 
     assert_eq!(rules.next(), None);
 
-    ast::Stmt::If(
+    Ok(ast::Stmt::If(
         ast::IfStmt {
-            if_test: parse_expr(test),
-            if_body: parse_block(block),
+            if_test: parse_expr(test)?,
+            if_body: parse_block(block)?,
             else_body
         }
-    )
+    ))
 }
 
-fn parse_block(pair: Pair<Rule>) -> Vec<ast::Stmt> {
+fn parse_try_stmt(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
+    assert_eq!(pair.as_rule(), Rule::try_stmt);
+    let span = pair.as_span();
+    let mut rules = pair.into_inner();
+    let body = parse_block(rules.next().unwrap())?;
+
+    let mut catch = None;
+    let mut finally = None;
+    for clause in rules {
+        match clause.as_rule() {
+            Rule::catch_clause => {
+                let mut rules = clause.into_inner().peekable();
+                let name = rules.next_if(|rule| rule.as_rule() == Rule::ident).map(|name| name.as_str().to_string());
+                catch = Some(ast::Catch { name, body: parse_block(rules.next().unwrap())? });
+            }
+            Rule::finally_clause => finally = Some(parse_block(clause.into_inner().next().unwrap())?),
+            _ => unreachable!(),
+        }
+    }
+    if catch.is_none() && finally.is_none() {
+        return Err(error_at(span, "try needs a catch or a finally block".to_string()));
+    }
+    Ok(ast::Stmt::Try(ast::TryStmt { body, catch, finally }))
+}
+
+/// Loop label without its quote, `'outer` is `outer`
+fn parse_label(rules: &mut Peekable<Pairs<Rule>>) -> Option<String> {
+    let label = rules.next_if(|rule| rule.as_rule() == Rule::label)?;
+    Some(label.as_str()[1..].to_string())
+}
+
+fn parse_while_loop(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
+    assert_eq!(pair.as_rule(), Rule::while_loop);
+    let mut rules = pair.into_inner().peekable();
+    let label = parse_label(&mut rules);
+    let test = parse_expr(rules.next().unwrap())?;
+    let body = parse_block(rules.next().unwrap())?;
+    assert_eq!(rules.next(), None);
+
+    Ok(ast::Stmt::While{label, test, body})
+}
+
+fn parse_for_loop(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
+    assert_eq!(pair.as_rule(), Rule::for_loop);
+    let mut rules = pair.into_inner().peekable();
+    let label = parse_label(&mut rules);
+    let pattern = parse_pattern(rules.next().unwrap());
+    let iter = parse_expr(rules.next().unwrap())?;
+    let body = parse_block(rules.next().unwrap())?;
+    assert_eq!(rules.next(), None);
+
+    Ok(ast::Stmt::For{label, pattern, iter, body})
+}
+
+fn parse_block(pair: Pair<Rule>) -> ParseResult<Vec<ast::Stmt>> {
     pair.into_inner().map(parse_stmt).collect()
 }
 
-fn parse_fun_decl(pair: Pair<Rule>) -> ast::Stmt {
+fn parse_import_stmt(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
+    assert_eq!(pair.as_rule(), Rule::import_stmt);
+    let mut rules = pair.into_inner();
+    let path = rules.next().unwrap().as_str().to_string();
+    let alias = match rules.next() {
+        Some(alias) => alias.as_str().to_string(),
+        None => path.rsplit('.').next().unwrap().to_string(),
+    };
+    assert_eq!(rules.next(), None);
+
+    Ok(ast::Stmt::Import{path, alias})
+}
+
+/// Consumes the optional leading `pub`
+fn parse_visibility(rules: &mut Pairs<Rule>) -> bool {
+    let public = rules.peek().is_some_and(|pair| pair.as_rule() == Rule::visibility);
+    if public {
+        rules.next();
+    }
+    public
+}
+
+fn parse_fun_decl(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
     assert_eq!(pair.as_rule(), Rule::fun_decl);
     let mut rules = pair.into_inner();
-    let ident = rules.next().unwrap().to_string();
+    let public = parse_visibility(&mut rules);
+    let ident = rules.next().unwrap().as_str().to_string();
+    let params = rules.next().unwrap().into_inner().map(|typed_ident| {
+        typed_ident.into_inner().next().unwrap().as_str().to_string()
+    });
+    let params = params.collect();
+    let return_type = rules.next().unwrap().as_str().to_string();
+    let body = parse_block(rules.next().unwrap())?;
 
     assert_eq!(rules.next(), None);
 
-    ast::Stmt::FunDecl{name: ident}
+    Ok(ast::Stmt::FunDecl{name: ident, params, return_type, body, public})
 }
 
-fn parse_var_decl(pair: Pair<Rule>) -> ast::Stmt {
+fn parse_return_stmt(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
+    assert_eq!(pair.as_rule(), Rule::return_stmt);
+    let mut values = parse_exprs(pair)?;
+
+    let value = match values.len() {
+        0 => None,
+        1 => values.pop(),
+        _ => Some(ast::Expr::Tuple(values)),
+    };
+    Ok(ast::Stmt::Return(value))
+}
+
+/// Target is a name, a field path or a path followed by indexes
+fn parse_assign_stmt(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
+    assert_eq!(pair.as_rule(), Rule::assign_stmt);
+    let mut rules = pair.into_inner();
+    let mut target = rules.next().unwrap().into_inner();
+    let path = target.next().unwrap().as_str().to_string();
+    let mut indexes = target.map(|index| parse_expr(index.into_inner().next().unwrap())).collect::<ParseResult<Vec<_>>>()?;
+
+    let target = match indexes.pop() {
+        Some(index) => {
+            let base = indexes.into_iter().fold(ast::Expr::VarCall{name: path}, |base, index| {
+                ast::Expr::Index { base: Box::new(base), index: Box::new(index) }
+            });
+            ast::Target::Index{base, index}
+        }
+        None => match path.rsplit_once('.') {
+            Some((base, name)) => ast::Target::Attr{base: ast::Expr::VarCall{name: base.to_string()}, name: name.to_string()},
+            None => ast::Target::Name(path),
+        },
+    };
+    let op = match rules.next().unwrap().as_str().strip_suffix('=').unwrap() {
+        "" => None,
+        "**" => Some(ast::BinOp::Exp),
+        "//" => Some(ast::BinOp::FloorDiv),
+        "<<" => Some(ast::BinOp::LShift),
+        ">>" => Some(ast::BinOp::RShift),
+        "+" => Some(ast::BinOp::Add),
+        "-" => Some(ast::BinOp::Sub),
+        "*" => Some(ast::BinOp::Mul),
+        "/" => Some(ast::BinOp::Div),
+        "%" => Some(ast::BinOp::Rem),
+        "&" => Some(ast::BinOp::BitAnd),
+        "|" => Some(ast::BinOp::BitOr),
+        "^" => Some(ast::BinOp::BitXor),
+        op => unreachable!("unknown assignment operator {}=", op),
+    };
+    let value = parse_expr(rules.next().unwrap())?;
+    assert_eq!(rules.next(), None);
+
+    Ok(ast::Stmt::Assign{target, op, value})
+}
+
+fn parse_var_decl(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
     assert_eq!(pair.as_rule(), Rule::var_decl);
     let mut rules = pair.into_inner();
-    let ident = rules.next().unwrap().as_str().to_string();
-    let expr = parse_expr(rules.next().unwrap());
+    let public = parse_visibility(&mut rules);
+    let pattern = parse_pattern(rules.next().unwrap());
+    let mut value = rules.next().unwrap();
+    if value.as_rule() == Rule::r#type {
+        value = rules.next().unwrap();
+    }
+    let expr = parse_expr(value)?;
+    assert_eq!(rules.next(), None);
+    Ok(ast::Stmt::VarDecl{pattern, value: expr, public})
+}
+
+fn parse_struct_decl(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
+    assert_eq!(pair.as_rule(), Rule::struct_decl);
+    let mut rules = pair.into_inner();
+    let public = parse_visibility(&mut rules);
+    let name = rules.next().unwrap().as_str().to_string();
+    let fields = rules.next().unwrap().into_inner().map(|field| {
+        let mut rules = field.into_inner();
+        let public = parse_visibility(&mut rules);
+        let name = rules.next().unwrap().as_str().to_string();
+        ast::Field{name, public}
+    });
+    let fields = fields.collect();
+    assert_eq!(rules.next(), None);
+    Ok(ast::Stmt::StructDecl{name, fields, public})
+}
+
+fn parse_enum_decl(pair: Pair<Rule>) -> ParseResult<ast::Stmt> {
+    assert_eq!(pair.as_rule(), Rule::enum_decl);
+    let mut rules = pair.into_inner();
+    let public = parse_visibility(&mut rules);
+    let name = rules.next().unwrap().as_str().to_string();
+    let variants = rules.next().unwrap().into_inner().map(|variant| {
+        let mut rules = variant.into_inner();
+        let name = rules.next().unwrap().as_str().to_string();
+        let fields = match rules.next() {
+            Some(params) => params.into_inner().map(|typed_ident| {
+                typed_ident.into_inner().next().unwrap().as_str().to_string()
+            }).collect(),
+            None => vec![],
+        };
+        ast::Variant{name, fields}
+    });
+    let variants = variants.collect();
     assert_eq!(rules.next(), None);
-    ast::Stmt::VarDecl{name: ident, value: expr}
+    Ok(ast::Stmt::EnumDecl{name, variants, public})
 }
 
-fn parse_stmts(pair: Pair<Rule>) -> Vec<ast::Stmt> {
+fn parse_pattern(pair: Pair<Rule>) -> ast::Pattern {
+    match pair.as_rule() {
+        Rule::ident => ast::Pattern::Ident(pair.as_str().to_string()),
+        Rule::wildcard => ast::Pattern::Wildcard,
+        Rule::tuple_pattern => ast::Pattern::Tuple(pair.into_inner().map(parse_pattern).collect()),
+        rule => unreachable!("Pattern::parse expected pattern, found {:?}", rule),
+    }
+}
+
+fn parse_stmts(pair: Pair<Rule>) -> ParseResult<Vec<ast::Stmt>> {
     assert_eq!(pair.as_rule(), Rule::stmts);
     pair.into_inner().map(parse_stmt).collect()
 }
 
 
-fn parse_module(pair: Pair<Rule>) -> ast::Module {
+fn parse_module(pair: Pair<Rule>) -> ParseResult<ast::Module> {
     let mut children = pair.into_inner();
 
     let stmts = children.next().unwrap();
     let _eoi = children.next().unwrap();
     assert_eq!(children.next(), None);
 
-    ast::Module {
-        stmts: parse_stmts(stmts)
-    }
+    Ok(ast::Module {
+        stmts: parse_stmts(stmts)?
+    })
 }
 
 
-pub fn parse_file(path: &str) -> ast::Module {
-    let source = fs::read_to_string(path).unwrap();
-    let parse_tree = Grammar::parse(Rule::module, &source).unwrap().next().unwrap();
-    let ast = parse_module(parse_tree);
-    ast
+pub fn parse_file(path: &str) -> Result<ast::Module, crate::error::Error> {
+    let source = fs::read_to_string(path)?;
+    Ok(parse_named_source(&source, path)?)
+}
+
+/// Same as `parse_source`, errors point at `path`
+pub fn parse_named_source(source: &str, path: &str) -> Result<ast::Module, ParseError> {
+    parse_source(source).map_err(|e| Box::new((*e).with_path(path)))
+}
+
+pub fn parse_source(source: &str) -> Result<ast::Module, ParseError> {
+    let parse_tree = Grammar::parse(Rule::module, source)?.next().unwrap();
+    parse_module(parse_tree)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_int(source: &str) -> ParseResult<ast::Expr> {
+        let pair = Grammar::parse(Rule::integer, source).unwrap().next().unwrap();
+        parse_integer(pair)
+    }
+
+    #[test]
+    fn integer_literals() {
+        assert!(matches!(parse_int("0xFF"), Ok(ast::Expr::Integer(255))));
+        assert!(matches!(parse_int("0o17"), Ok(ast::Expr::Integer(15))));
+        assert!(matches!(parse_int("0b1010"), Ok(ast::Expr::Integer(10))));
+        assert!(matches!(parse_int("1_000_000"), Ok(ast::Expr::Integer(1_000_000))));
+        assert!(matches!(parse_int("9223372036854775807"), Ok(ast::Expr::Integer(i64::MAX))));
+        assert!(parse_int("9223372036854775808").is_err());
+    }
+
+    fn first_stmt(source: &str) -> Rule {
+        let module = Grammar::parse(Rule::module, source).unwrap().next().unwrap();
+        module.into_inner().next().unwrap().into_inner().next().unwrap().as_rule()
+    }
+
+    #[test]
+    fn keywords_end_at_word_boundary() {
+        assert_eq!(first_stmt("let x = 2"), Rule::var_decl);
+        assert_eq!(first_stmt("let(x, y) = (1, 2)"), Rule::var_decl);
+        assert_eq!(first_stmt("letter = 2"), Rule::assign_stmt);
+        assert_eq!(first_stmt("return x"), Rule::return_stmt);
+        assert_eq!(first_stmt("returned = 5"), Rule::assign_stmt);
+        assert_eq!(first_stmt("import a.b as c"), Rule::import_stmt);
+        assert_eq!(first_stmt("important = 2"), Rule::assign_stmt);
+        assert_eq!(first_stmt("throw x"), Rule::throw_stmt);
+        assert_eq!(first_stmt("thrown = 1"), Rule::assign_stmt);
+        assert!(Grammar::parse(Rule::module, "import a\nassert = 1").is_ok());
+    }
 }
@@ -0,0 +1,149 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use nightjar::{ast, code_obj, parser, Engine, Error, Value};
+
+use crate::format::Brackets;
+
+const HISTORY_FILE: &str = ".nightjar_history";
+
+const HELP: &str = "\
+:ast      toggle printing the AST of every input
+:dis      toggle disassembling every input
+:vars     show global variables
+:history  show previous inputs
+:help     show this message
+:quit     exit, as does end of input";
+
+/// Interactive session, every input is compiled into the same main module
+struct Repl {
+    engine: Engine,
+    ast_verbose: bool,
+    dis_verbose: bool,
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+}
+
+pub fn run() {
+    let history_path = std::env::var_os("HOME").map(|home| Path::new(&home).join(HISTORY_FILE));
+    let history = history_path.as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|text| text.lines().map(str::to_string).collect())
+        .unwrap_or_default();
+    let mut repl = Repl { engine: Engine::new(), ast_verbose: false, dis_verbose: false, history, history_path };
+
+    println!("Nightjar REPL, :help for commands");
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    while let Some(input) = read_input(&mut lines) {
+        let input = input.trim();
+        if input.is_empty() {
+            continue;
+        }
+        repl.add_history(input);
+
+        match input {
+            ":quit" => break,
+            ":help" => println!("{}", HELP),
+            ":ast" => {
+                repl.ast_verbose = !repl.ast_verbose;
+                println!("AST printing {}", if repl.ast_verbose { "on" } else { "off" });
+            }
+            ":dis" => {
+                repl.dis_verbose = !repl.dis_verbose;
+                println!("Disassembly {}", if repl.dis_verbose { "on" } else { "off" });
+            }
+            ":vars" => {
+                for (name, value) in repl.engine.globals() {
+                    println!("{} = {}", name, value.__repr__());
+                }
+            }
+            ":history" => {
+                for (i, line) in repl.history.iter().enumerate() {
+                    println!("{:>4}  {}", i + 1, line);
+                }
+            }
+            command if command.starts_with(':') => println!("Unknown command {}, try :help", command),
+            source => match repl.eval(source) {
+                Ok(Value::None) => {}
+                Ok(value) => println!("{}", value.__repr__()),
+                Err(err) => match err.exit_status() {
+                    Some(code) => std::process::exit(code),
+                    None => eprintln!("{}", err),
+                },
+            },
+        }
+    }
+}
+
+impl Repl {
+    fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        let module = parser::parse_named_source(source, "<stdin>")?;
+        if self.ast_verbose {
+            ast::pretty_print(&module);
+        }
+        let co = self.engine.compile(Path::new("<stdin>"), &module)?;
+        if self.dis_verbose {
+            code_obj::dis(&co);
+        }
+        self.engine.run(co)
+    }
+
+    /// History is kept across sessions, failing to save it is not worth an error
+    fn add_history(&mut self, input: &str) {
+        self.history.push(input.to_string());
+        if let Some(path) = &self.history_path {
+            let file = fs::OpenOptions::new().create(true).append(true).open(path);
+            if let Ok(mut file) = file {
+                let _ = writeln!(file, "{}", input.replace('\n', " "));
+            }
+        }
+    }
+}
+
+/// Reads lines until brackets are balanced, `None` at end of input
+fn read_input(lines: &mut impl Iterator<Item = io::Result<String>>) -> Option<String> {
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { ">>> " } else { "... " });
+        io::stdout().flush().ok();
+
+        match lines.next() {
+            Some(Ok(line)) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            _ if input.is_empty() => {
+                println!();
+                return None;
+            }
+            // Unfinished input is still evaluated to report the error
+            _ => return Some(input),
+        }
+        if !is_incomplete(&input) {
+            return Some(input);
+        }
+    }
+}
+
+/// Input with unclosed brackets or strings continues on the next line
+fn is_incomplete(source: &str) -> bool {
+    let mut brackets = Brackets::default();
+    brackets.scan(source);
+    brackets.depth > 0 || brackets.in_string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incomplete_input() {
+        assert!(is_incomplete("fun f(): int {\n"));
+        assert!(is_incomplete("let x = [1,\n"));
+        assert!(!is_incomplete("let s = \"{\"\n"));
+        assert!(!is_incomplete("let x = 1 # {\n"));
+        assert!(!is_incomplete("fun f(): int {\n    return 1\n}\n"));
+    }
+}
@@ -1,80 +1,965 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::code_obj::CodeObject;
+use crate::error::{ErrorKind, RuntimeError};
+use crate::native::Native;
+
+/// Largest left shift of an int, the result would take 2 MiB
+const MAX_SHIFT: u64 = 1 << 24;
+
 #[derive(Debug, Clone)]
+#[allow(clippy::box_collection)] // Keeps `Value` two words wide, see `value_size` test
 pub enum Value {
     Integer(i64),
+    /// Only holds values outside of `i64` range, smaller results are demoted back to `Integer`
+    BigInt(Box<BigInt>),
     String(Box<String>),
     Float(f64),
+    None,
+    Tuple(Rc<Vec<Value>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<BTreeMap<String, Value>>>),
+    /// Compiled function or module body, only appears as a constant
+    Code(Rc<CodeObject>),
+    Function(Rc<Function>),
+    /// Host function, usually a member of a native module
+    Native(Rc<Native>),
+    Module(Rc<Module>),
+    StructType(Rc<StructType>),
+    Struct(Rc<Struct>),
+    EnumType(Rc<EnumType>),
+    Variant(Rc<Variant>),
+    /// Snapshot of a collection being walked by a `for` loop
+    Iterator(Box<std::vec::IntoIter<Value>>),
+    /// Handle returned by `fs.open`, a `for` loop reads it line by line
+    File(Rc<FileHandle>),
+}
+
+/// Function bound to the module whose globals it sees
+#[derive(Debug)]
+pub struct Function {
+    pub co: Rc<CodeObject>,
+    pub module: Rc<Module>,
+}
+
+/// Executed module, its globals are addressed by index and named by `names`
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+    /// Grows when more code is compiled into the module, see `Engine::eval_str`
+    pub names: RefCell<Vec<String>>,
+    pub exports: Vec<String>,
+    pub globals: RefCell<Vec<Value>>,
+}
+
+impl Module {
+    pub fn new(co: &CodeObject) -> Self {
+        let globals = RefCell::new(vec![Value::None; co.names.len()]);
+        Module { name: co.name.clone(), names: RefCell::new(co.names.clone()), exports: co.exports.clone(), globals }
+    }
+
+    /// Makes room for globals first introduced by `co`, existing slots keep their values
+    pub fn extend(&self, co: &CodeObject) {
+        let mut names = self.names.borrow_mut();
+        if co.names.len() > names.len() {
+            *names = co.names.clone();
+            self.globals.borrow_mut().resize(names.len(), Value::None);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let pos = self.position(name)?;
+        Some(self.globals.borrow()[pos].clone())
+    }
+
+    /// Sets global `name`, declaring it if it does not exist yet
+    pub fn set(&self, name: &str, value: Value) {
+        let pos = self.position(name).unwrap_or_else(|| {
+            self.names.borrow_mut().push(name.to_string());
+            self.globals.borrow_mut().push(Value::None);
+            self.globals.borrow().len() - 1
+        });
+        self.globals.borrow_mut()[pos] = value;
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.names.borrow().iter().position(|n| n == name)
+    }
+}
+
+/// Declared struct, private fields are only reachable from code of `module`
+#[derive(Debug)]
+pub struct StructType {
+    pub name: String,
+    pub module: String,
+    pub fields: Vec<FieldDef>,
+}
+
+#[derive(Debug)]
+pub struct FieldDef {
+    pub name: String,
+    pub public: bool,
+}
+
+/// Struct instance, fields are stored in declaration order
+#[derive(Debug)]
+pub struct Struct {
+    pub ty: Rc<StructType>,
+    pub fields: RefCell<Vec<Value>>,
+}
+
+impl StructType {
+    /// Builds an instance from named field values, every field must be given exactly once
+    pub fn instantiate(ty: &Rc<StructType>, names: &[Value], values: Vec<Value>, from: &str) -> Result<Value, RuntimeError> {
+        let mut fields = vec![None; ty.fields.len()];
+        for (name, value) in names.iter().zip(values) {
+            let name = name.__str__();
+            let pos = ty.field(&name, from)?;
+            if fields[pos].replace(value).is_some() {
+                return Err(RuntimeError::new(ErrorKind::Type, format!("field '{}' of {} is given twice", name, ty.name)));
+            }
+        }
+        let fields: Option<Vec<Value>> = fields.into_iter().collect();
+        let fields = fields.ok_or_else(|| {
+            RuntimeError::new(ErrorKind::Type, format!("{} is missing fields", ty.name))
+        })?;
+        Ok(Value::Struct(Rc::new(Struct { ty: ty.clone(), fields: RefCell::new(fields) })))
+    }
+
+    /// Position of field `name` as seen from code of module `from`
+    pub fn field(&self, name: &str, from: &str) -> Result<usize, RuntimeError> {
+        let pos = self.fields.iter().position(|field| field.name == name).ok_or_else(|| {
+            RuntimeError::new(ErrorKind::Name, format!("{} has no field '{}'", self.name, name))
+        })?;
+        if !self.fields[pos].public && self.module != from {
+            return Err(RuntimeError::new(ErrorKind::Name, format!("field '{}' of {} is private", name, self.name)));
+        }
+        Ok(pos)
+    }
+}
+
+impl Struct {
+    /// Field lookup for host code, which sees private fields too
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let pos = self.ty.fields.iter().position(|field| field.name == name)?;
+        Some(self.fields.borrow()[pos].clone())
+    }
+}
+
+/// Declared enum, each variant has a name and names of the values it carries
+#[derive(Debug)]
+pub struct EnumType {
+    pub name: String,
+    pub variants: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    pub ty: Rc<EnumType>,
+    pub index: usize,
+    pub values: Vec<Value>,
+}
+
+impl EnumType {
+    pub fn construct(ty: &Rc<EnumType>, name: &str, values: Vec<Value>) -> Result<Value, RuntimeError> {
+        let index = ty.variants.iter().position(|(variant, _)| variant == name).ok_or_else(|| {
+            RuntimeError::new(ErrorKind::Name, format!("{} has no variant '{}'", ty.name, name))
+        })?;
+        let arity = ty.variants[index].1.len();
+        if arity != values.len() {
+            let message = format!("{}.{} takes {} values but {} were given", ty.name, name, arity, values.len());
+            return Err(RuntimeError::new(ErrorKind::Type, message));
+        }
+        Ok(Value::Variant(Rc::new(Variant { ty: ty.clone(), index, values })))
+    }
+
+    /// Built-in `Result` enum, `Ok(value)` or `Err(error)`, which `?` unwraps
+    pub fn result() -> Rc<EnumType> {
+        thread_local! {
+            static RESULT: Rc<EnumType> = Rc::new(EnumType {
+                name: "Result".to_string(),
+                variants: vec![("Ok".to_string(), vec!["value".to_string()]), ("Err".to_string(), vec!["error".to_string()])],
+            });
+        }
+        RESULT.with(|ty| ty.clone())
+    }
+}
+
+impl Variant {
+    pub fn is_result(&self) -> bool {
+        Rc::ptr_eq(&self.ty, &EnumType::result())
+    }
+
+    /// `Ok` of a `Result`, the other variant being `Err`
+    pub fn is_ok(&self) -> bool {
+        self.is_result() && self.index == 0
+    }
+}
+
+/// Open file, either for reading or for writing depending on the mode it was opened with
+#[derive(Debug)]
+pub struct FileHandle {
+    pub path: String,
+    stream: RefCell<Stream>,
+}
+
+#[derive(Debug)]
+enum Stream {
+    Reader(BufReader<fs::File>),
+    Writer(fs::File),
+    Closed,
+}
+
+impl FileHandle {
+    /// Modes are `r` to read, `w` to truncate and write and `a` to append
+    pub fn open(path: &str, mode: &str) -> Result<Self, RuntimeError> {
+        let mut options = fs::OpenOptions::new();
+        match mode {
+            "r" => options.read(true),
+            "w" => options.write(true).create(true).truncate(true),
+            "a" => options.append(true).create(true),
+            _ => return Err(RuntimeError::new(ErrorKind::Value, format!("invalid file mode '{}'", mode))),
+        };
+        let file = options.open(path).map_err(|err| RuntimeError::io_at(path, err))?;
+        let stream = match mode {
+            "r" => Stream::Reader(BufReader::new(file)),
+            _ => Stream::Writer(file),
+        };
+        Ok(FileHandle { path: path.to_string(), stream: RefCell::new(stream) })
+    }
+
+    /// Next line without its terminator, `None` at end of file
+    pub fn read_line(&self) -> Result<Option<String>, RuntimeError> {
+        let mut stream = self.stream.borrow_mut();
+        let reader = match &mut *stream {
+            Stream::Reader(reader) => reader,
+            stream => return Err(self.mode_error(stream, "reading")),
+        };
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|err| RuntimeError::io_at(&self.path, err))? == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    pub fn write(&self, text: &str) -> Result<(), RuntimeError> {
+        let mut stream = self.stream.borrow_mut();
+        let file = match &mut *stream {
+            Stream::Writer(file) => file,
+            stream => return Err(self.mode_error(stream, "writing")),
+        };
+        file.write_all(text.as_bytes()).map_err(|err| RuntimeError::io_at(&self.path, err))
+    }
+
+    /// Closing twice is allowed, any later read or write fails
+    pub fn close(&self) {
+        *self.stream.borrow_mut() = Stream::Closed;
+    }
+
+    /// Called with the stream still borrowed, so it is passed in
+    fn mode_error(&self, stream: &Stream, operation: &str) -> RuntimeError {
+        let message = match stream {
+            Stream::Closed => format!("file {} is closed", self.path),
+            _ => format!("file {} is not open for {}", self.path, operation),
+        };
+        RuntimeError::new(ErrorKind::IO, message)
+    }
 }
 
 impl Value {
-    pub fn __add__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer(a + b)
-    }
-    pub fn __sub__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer(a - b)
-    }
-    pub fn __mul__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer(a * b)
-    }
-    pub fn __div__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer(a / b)
-    }
-    pub fn __gt__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer((a > b) as i64)
-    }
-    pub fn __lt__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer((a < b) as i64)
-    }
-    pub fn __et__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer((a == b) as i64)
-    }
-    pub fn __ne__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer((a != b) as i64)
-    }
-    pub fn __ge__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer((a >= b) as i64)
-    }
-    pub fn __le__(self, other: Self) -> Self {
-        let a = self.as_int();
-        let b = other.as_int();
-        Value::Integer((a <= b) as i64)
+    pub fn __add__(self, other: Self) -> Result<Self, RuntimeError> {
+        self.arithmetic(&other, "+", i64::checked_add, |a, b| a + b, |a, b| a + b)
+    }
+    pub fn __sub__(self, other: Self) -> Result<Self, RuntimeError> {
+        self.arithmetic(&other, "-", i64::checked_sub, |a, b| a - b, |a, b| a - b)
+    }
+    pub fn __mul__(self, other: Self) -> Result<Self, RuntimeError> {
+        self.arithmetic(&other, "*", i64::checked_mul, |a, b| a * b, |a, b| a * b)
+    }
+    /// True division, always produces a float
+    pub fn __div__(self, other: Self) -> Result<Self, RuntimeError> {
+        if other.is_zero() {
+            return Err(RuntimeError::zero_division());
+        }
+        match (self.as_float(), other.as_float()) {
+            (Some(a), Some(b)) => Ok(Value::Float(a / b)),
+            _ => Err(self.operand_error("/", &other)),
+        }
+    }
+    /// Division rounded towards negative infinity
+    pub fn __floordiv__(self, other: Self) -> Result<Self, RuntimeError> {
+        if other.is_zero() {
+            return Err(RuntimeError::zero_division());
+        }
+        self.arithmetic(
+            &other,
+            "//",
+            |a, b| Some(floor_div_rem(a, b)?.0),
+            |a, b| a.div_rem_floor(b).unwrap().0,
+            |a, b| (a / b).floor(),
+        )
+    }
+    /// Modulo, the result has the sign of the divisor
+    pub fn __mod__(self, other: Self) -> Result<Self, RuntimeError> {
+        if other.is_zero() {
+            return Err(RuntimeError::zero_division());
+        }
+        self.arithmetic(
+            &other,
+            "%",
+            |a, b| Some(floor_div_rem(a, b)?.1),
+            |a, b| a.div_rem_floor(b).unwrap().1,
+            |a, b| {
+                let r = a % b;
+                if r != 0.0 && (r < 0.0) != (b < 0.0) { r + b } else { r }
+            },
+        )
+    }
+    /// Exact for an int raised to a non-negative int, a float otherwise
+    pub fn __pow__(self, other: Self) -> Result<Self, RuntimeError> {
+        match (&self, &other) {
+            (Value::Integer(_) | Value::BigInt(_), Value::Integer(exponent)) if *exponent >= 0 => {
+                let mut result = Value::Integer(1);
+                let mut square = self;
+                let mut exponent = *exponent;
+                while exponent > 0 {
+                    if exponent & 1 == 1 {
+                        result = result.__mul__(square.clone())?;
+                    }
+                    exponent >>= 1;
+                    if exponent > 0 {
+                        square = square.clone().__mul__(square)?;
+                    }
+                }
+                Ok(result)
+            }
+            (Value::Integer(_) | Value::BigInt(_), Value::BigInt(exponent)) if !exponent.is_negative() => {
+                Err(RuntimeError::new(ErrorKind::Value, "integer exponent too large"))
+            }
+            _ => match (self.as_float(), other.as_float()) {
+                (Some(a), Some(b)) if a == 0.0 && b < 0.0 => Err(RuntimeError::zero_division()),
+                (Some(a), Some(b)) => Ok(Value::Float(a.powf(b))),
+                _ => Err(self.operand_error("**", &other)),
+            },
+        }
+    }
+    pub fn __lshift__(self, other: Self) -> Result<Self, RuntimeError> {
+        let count = self.shift_count(&other, "<<")?;
+        match self {
+            Value::Integer(x) if count < 64 && (x << count) >> count == x => Ok(Value::Integer(x << count)),
+            _ if self.is_zero() => Ok(Value::Integer(0)),
+            _ if count > MAX_SHIFT => Err(RuntimeError::new(ErrorKind::Value, "shift count too large")),
+            _ => Ok(Value::from_bigint(self.to_bigint().shl(count as u32))),
+        }
+    }
+    /// Rounds towards negative infinity, as shifting the two's complement would
+    pub fn __rshift__(self, other: Self) -> Result<Self, RuntimeError> {
+        let count = self.shift_count(&other, ">>")?;
+        match self {
+            Value::Integer(x) => Ok(Value::Integer(x >> count.min(63))),
+            _ => Ok(Value::from_bigint(self.to_bigint().shr(count.min(u32::MAX as u64) as u32))),
+        }
+    }
+    pub fn __and__(self, other: Self) -> Result<Self, RuntimeError> {
+        self.bitwise(&other, "&", |a, b| a & b, |a, b| a & b)
+    }
+    pub fn __or__(self, other: Self) -> Result<Self, RuntimeError> {
+        self.bitwise(&other, "|", |a, b| a | b, |a, b| a | b)
+    }
+    pub fn __xor__(self, other: Self) -> Result<Self, RuntimeError> {
+        self.bitwise(&other, "^", |a, b| a ^ b, |a, b| a ^ b)
+    }
+    pub fn __neg__(self) -> Result<Self, RuntimeError> {
+        match self {
+            Value::Integer(x) => Ok(x.checked_neg().map_or_else(|| Value::from_bigint(-&BigInt::from(x)), Value::Integer)),
+            Value::BigInt(x) => Ok(Value::from_bigint(-&*x)),
+            Value::Float(f) => Ok(Value::Float(-f)),
+            _ => Err(RuntimeError::new(ErrorKind::Type, format!("bad operand type for unary -: '{}'", self.type_name()))),
+        }
+    }
+    /// Bitwise not, `~x` is `-x - 1`
+    pub fn __invert__(self) -> Result<Self, RuntimeError> {
+        match self {
+            Value::Integer(x) => Ok(Value::Integer(!x)),
+            Value::BigInt(x) => Ok(Value::from_bigint(&-&*x - &BigInt::from(1))),
+            _ => Err(RuntimeError::new(ErrorKind::Type, format!("bad operand type for unary ~: '{}'", self.type_name()))),
+        }
+    }
+    pub fn __gt__(self, other: Self) -> Result<Self, RuntimeError> {
+        let ord = self.compare(&other, ">")?;
+        Ok(Value::Integer(ord.is_some_and(Ordering::is_gt) as i64))
     }
+    pub fn __lt__(self, other: Self) -> Result<Self, RuntimeError> {
+        let ord = self.compare(&other, "<")?;
+        Ok(Value::Integer(ord.is_some_and(Ordering::is_lt) as i64))
+    }
+    pub fn __et__(self, other: Self) -> Result<Self, RuntimeError> {
+        Ok(Value::Integer(self.equals(&other) as i64))
+    }
+    pub fn __ne__(self, other: Self) -> Result<Self, RuntimeError> {
+        Ok(Value::Integer(!self.equals(&other) as i64))
+    }
+    pub fn __ge__(self, other: Self) -> Result<Self, RuntimeError> {
+        let ord = self.compare(&other, ">=")?;
+        Ok(Value::Integer(ord.is_some_and(Ordering::is_ge) as i64))
+    }
+    pub fn __le__(self, other: Self) -> Result<Self, RuntimeError> {
+        let ord = self.compare(&other, "<=")?;
+        Ok(Value::Integer(ord.is_some_and(Ordering::is_le) as i64))
+    }
+    /// Display form used by `print` and f-strings, strings appear as they are
+    pub fn __str__(&self) -> String {
+        match self {
+            Value::String(s) => *s.clone(),
+            value => value.__repr__(),
+        }
+    }
+    /// Debug form, strings are quoted and escaped, so are items of
+    /// collections and fields of structs
     pub fn __repr__(&self) -> String {
         match self {
             Value::Integer(x) => format!("{}", x),
-            Value::Float(f) => format!("{}", f),
-            Value::String(s) => *s.clone(),
+            Value::BigInt(x) => format!("{}", x),
+            Value::Float(f) if f.is_nan() => "nan".to_string(),
+            Value::Float(f) => format!("{:?}", f),
+            Value::String(s) => quote(s),
+            Value::None => "none".to_string(),
+            Value::Tuple(items) if items.len() == 1 => format!("({},)", items[0].__repr__()),
+            Value::Tuple(items) => format!("({})", join_repr(items.iter())),
+            Value::List(items) => format!("[{}]", join_repr(items.borrow().iter())),
+            Value::Map(entries) => {
+                let entries = entries.borrow();
+                let entries: Vec<String> = entries.iter().map(|(k, v)| format!("{}: {}", quote(k), v.__repr__())).collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Value::Code(co) => format!("<code {}>", co.name),
+            Value::Function(function) => format!("<fun {}>", function.co.name),
+            Value::Native(native) => format!("<native fun {}>", native.name),
+            Value::Module(module) => format!("<module {}>", module.name),
+            Value::StructType(ty) => format!("<struct {}>", ty.name),
+            Value::Struct(instance) => {
+                let fields = instance.fields.borrow();
+                let fields: Vec<String> = instance.ty.fields.iter().zip(fields.iter())
+                    .map(|(field, value)| format!("{}: {}", field.name, value.__repr__()))
+                    .collect();
+                if fields.is_empty() {
+                    return format!("{} {{}}", instance.ty.name);
+                }
+                format!("{} {{ {} }}", instance.ty.name, fields.join(", "))
+            }
+            Value::EnumType(ty) => format!("<enum {}>", ty.name),
+            Value::Variant(variant) => {
+                let name = &variant.ty.variants[variant.index].0;
+                if variant.is_result() {
+                    // Written the way scripts build them
+                    format!("{}({})", name, join_repr(variant.values.iter()))
+                } else if variant.values.is_empty() {
+                    format!("{}.{}", variant.ty.name, name)
+                } else {
+                    format!("{}.{}({})", variant.ty.name, name, join_repr(variant.values.iter()))
+                }
+            }
+            Value::Iterator(_) => "<iterator>".to_string(),
+            Value::File(file) => format!("<file {}>", file.path),
+        }
+    }
+    /// Items visited by a `for` loop, maps yield their keys
+    pub fn __iter__(&self) -> Result<Self, RuntimeError> {
+        let items = match self {
+            Value::Tuple(items) => items.to_vec(),
+            Value::List(items) => items.borrow().clone(),
+            Value::Map(entries) => entries.borrow().keys().map(|k| Value::String(Box::new(k.clone()))).collect(),
+            Value::String(s) => s.chars().map(|c| Value::String(Box::new(c.to_string()))).collect(),
+            Value::Iterator(_) | Value::File(_) => return Ok(self.clone()),
+            _ => return Err(RuntimeError::new(ErrorKind::Type, format!("'{}' is not iterable", self.type_name()))),
+        };
+        Ok(Value::Iterator(Box::new(items.into_iter())))
+    }
+    /// Items of a tuple or list destructured into exactly `count` bindings
+    pub fn unpack(&self, count: usize) -> Result<Vec<Self>, RuntimeError> {
+        let items = match self {
+            Value::Tuple(items) => items.to_vec(),
+            Value::List(items) => items.borrow().clone(),
+            _ => return Err(RuntimeError::new(ErrorKind::Type, format!("cannot unpack '{}'", self.type_name()))),
+        };
+        if items.len() != count {
+            let message = format!("expected {} values to unpack, got {}", count, items.len());
+            return Err(RuntimeError::new(ErrorKind::Value, message));
         }
+        Ok(items)
     }
-    pub fn as_bool(self) -> bool {
+    /// Member lookup done by code of module `from`, which decides if private struct fields are visible
+    pub fn get_attr(&self, name: &str, from: &str) -> Result<Self, RuntimeError> {
         match self {
-            Value::Integer(v) => v > 0,
-            _ => panic!("Expected integer"),
+            Value::Module(module) if module.position(name).is_some() && !module.exports.iter().any(|n| n == name) => {
+                Err(RuntimeError::new(ErrorKind::Name, format!("'{}' is private in module {}", name, module.name)))
+            }
+            Value::Module(module) => module.get(name).ok_or_else(|| {
+                RuntimeError::new(ErrorKind::Name, format!("module {} has no member '{}'", module.name, name))
+            }),
+            Value::Struct(instance) => {
+                let pos = instance.ty.field(name, from)?;
+                Ok(instance.fields.borrow()[pos].clone())
+            }
+            Value::EnumType(ty) => EnumType::construct(ty, name, vec![]),
+            Value::Variant(variant) => {
+                let fields = &variant.ty.variants[variant.index].1;
+                match fields.iter().position(|field| field == name) {
+                    Some(pos) => Ok(variant.values[pos].clone()),
+                    None => Err(RuntimeError::new(ErrorKind::Name, format!("{} has no field '{}'", self.__repr__(), name))),
+                }
+            }
+            _ => Err(RuntimeError::new(ErrorKind::Type, format!("'{}' has no attribute '{}'", self.type_name(), name))),
+        }
+    }
+    /// Field assignment done by code of module `from`, only struct fields can be assigned
+    pub fn set_attr(&self, name: &str, value: Self, from: &str) -> Result<(), RuntimeError> {
+        match self {
+            Value::Struct(instance) => {
+                let pos = instance.ty.field(name, from)?;
+                instance.fields.borrow_mut()[pos] = value;
+                Ok(())
+            }
+            _ => Err(RuntimeError::new(ErrorKind::Type, format!("cannot assign attribute '{}' of '{}'", name, self.type_name()))),
+        }
+    }
+    pub fn call_method(&self, name: &str, args: Vec<Self>) -> Result<Self, RuntimeError> {
+        let string = |s: &String| Value::String(Box::new(s.clone()));
+        match (self, name, args.as_slice()) {
+            (Value::EnumType(ty), _, _) => EnumType::construct(ty, name, args),
+            (Value::Variant(variant), "is_ok", []) if variant.is_result() => Ok(Value::Integer(variant.is_ok() as i64)),
+            (Value::Variant(variant), "is_err", []) if variant.is_result() => Ok(Value::Integer(!variant.is_ok() as i64)),
+            (Value::Variant(variant), "unwrap", []) if variant.is_result() => match variant.is_ok() {
+                true => Ok(variant.values[0].clone()),
+                false => Err(RuntimeError::new(ErrorKind::Value, format!("unwrap called on {}", self.__repr__()))),
+            },
+            (Value::Variant(variant), "unwrap_or", [default]) if variant.is_result() => match variant.is_ok() {
+                true => Ok(variant.values[0].clone()),
+                false => Ok(default.clone()),
+            },
+            (Value::Map(entries), "keys", []) => {
+                Ok(Value::new_list(entries.borrow().keys().map(string).collect()))
+            }
+            (Value::Map(entries), "values", []) => {
+                Ok(Value::new_list(entries.borrow().values().cloned().collect()))
+            }
+            (Value::Map(entries), "items", []) => {
+                let entries = entries.borrow();
+                let items = entries.iter().map(|(k, v)| Value::Tuple(Rc::new(vec![string(k), v.clone()])));
+                Ok(Value::new_list(items.collect()))
+            }
+            _ => {
+                let message = format!("'{}' has no method '{}' taking {} arguments", self.type_name(), name, args.len());
+                Err(RuntimeError::new(ErrorKind::Type, message))
+            }
+        }
+    }
+    pub fn new_list(items: Vec<Self>) -> Self {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+    pub fn new_map(entries: Vec<(Self, Self)>) -> Result<Self, RuntimeError> {
+        let mut map = BTreeMap::new();
+        for (key, value) in entries {
+            match key {
+                Value::String(key) => map.insert(*key, value),
+                _ => return Err(RuntimeError::new(ErrorKind::Type, format!("map keys must be strings, not '{}'", key.type_name()))),
+            };
+        }
+        Ok(Value::Map(Rc::new(RefCell::new(map))))
+    }
+    /// Format value according to f-string spec `[[fill]align][+][0][width][.precision]`,
+    /// spec is already validated by the grammar except for the size of numbers
+    pub fn __format__(&self, spec: &str) -> Result<String, RuntimeError> {
+        let spec = FormatSpec::parse(spec)?;
+
+        let mut body = match (self, spec.precision) {
+            (Value::Integer(x), Some(p)) => format!("{:.*}", p, *x as f64),
+            (Value::BigInt(x), Some(p)) => format!("{:.*}", p, x.to_f64()),
+            (Value::Float(f), Some(p)) => format!("{:.*}", p, f),
+            (Value::String(s), Some(p)) => s.chars().take(p).collect(),
+            (value, _) => value.__str__(),
+        };
+
+        let is_number = matches!(self, Value::Integer(_) | Value::BigInt(_) | Value::Float(_));
+        if is_number && spec.sign && !body.starts_with('-') {
+            body.insert(0, '+');
+        }
+
+        let len = body.chars().count();
+        if len >= spec.width {
+            return Ok(body);
+        }
+        let padding = spec.width - len;
+
+        if is_number && spec.zero && spec.align.is_none() {
+            let sign_len = if body.starts_with(['+', '-']) { 1 } else { 0 };
+            body.insert_str(sign_len, &"0".repeat(padding));
+            return Ok(body);
+        }
+
+        let align = spec.align.unwrap_or(if is_number { '>' } else { '<' });
+        let fill = |n: usize| spec.fill.to_string().repeat(n);
+        Ok(match align {
+            '<' => body + &fill(padding),
+            '>' => fill(padding) + &body,
+            '^' => fill(padding / 2) + &body + &fill(padding - padding / 2),
+            _ => unreachable!(),
+        })
+    }
+    /// Truthiness, none, zero and empty strings and collections are false
+    pub fn as_bool(&self) -> bool {
+        match self {
+            Value::Integer(v) => *v != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::String(s) => !s.is_empty(),
+            Value::None => false,
+            Value::Tuple(items) => !items.is_empty(),
+            Value::List(items) => !items.borrow().is_empty(),
+            Value::Map(entries) => !entries.borrow().is_empty(),
+            _ => true,
+        }
+    }
+    /// Characters of a string or items of a collection
+    pub fn __len__(&self) -> Result<usize, RuntimeError> {
+        match self {
+            Value::String(s) => Ok(s.chars().count()),
+            Value::Tuple(items) => Ok(items.len()),
+            Value::List(items) => Ok(items.borrow().len()),
+            Value::Map(entries) => Ok(entries.borrow().len()),
+            value => Err(RuntimeError::new(ErrorKind::Type, format!("'{}' has no length", value.type_name()))),
+        }
+    }
+    /// Item of a sequence, negative indexes count from the end, or value of a map key
+    pub fn __getitem__(&self, index: &Self) -> Result<Self, RuntimeError> {
+        match self {
+            Value::List(items) => {
+                let items = items.borrow();
+                Ok(items[self.position(index, items.len())?].clone())
+            }
+            Value::Tuple(items) => Ok(items[self.position(index, items.len())?].clone()),
+            Value::String(s) => {
+                let pos = self.position(index, s.chars().count())?;
+                Ok(Value::String(Box::new(s.chars().nth(pos).unwrap().to_string())))
+            }
+            Value::Map(entries) => {
+                let key = map_key(index)?;
+                entries.borrow().get(key).cloned().ok_or_else(|| {
+                    RuntimeError::new(ErrorKind::Key, format!("key {} not found", index.__repr__()))
+                })
+            }
+            _ => Err(RuntimeError::new(ErrorKind::Type, format!("'{}' is not indexable", self.type_name()))),
         }
     }
-    pub fn as_int(self) -> i64 {
+    /// Replaces an item of a list or sets a map key
+    pub fn __setitem__(&self, index: &Self, value: Self) -> Result<(), RuntimeError> {
         match self {
-            Value::Integer(v) => v,
+            Value::List(items) => {
+                let mut items = items.borrow_mut();
+                let pos = self.position(index, items.len())?;
+                items[pos] = value;
+            }
+            Value::Map(entries) => {
+                let key = map_key(index)?;
+                entries.borrow_mut().insert(key.clone(), value);
+            }
+            _ => return Err(RuntimeError::new(ErrorKind::Type, format!("'{}' does not support item assignment", self.type_name()))),
+        }
+        Ok(())
+    }
+    /// Checked position of `index` in a sequence of `len` items
+    fn position(&self, index: &Self, len: usize) -> Result<usize, RuntimeError> {
+        let Value::Integer(index) = index else {
+            return Err(RuntimeError::new(ErrorKind::Type, format!("{} indexes must be int, not '{}'", self.type_name(), index.type_name())));
+        };
+        let pos = if *index < 0 { index.checked_add(len as i64) } else { Some(*index) };
+        match pos {
+            Some(pos) if pos >= 0 && (pos as usize) < len => Ok(pos as usize),
+            _ => Err(RuntimeError::new(ErrorKind::Index, format!("{} index {} out of range", self.type_name(), index))),
+        }
+    }
+    /// Floats are truncated, strings are parsed in `radix` with an optional
+    /// sign, surrounding whitespace and `_` separators
+    pub fn __int__(&self, radix: u32) -> Result<Self, RuntimeError> {
+        match self {
+            Value::String(s) => {
+                if !(2..=36).contains(&radix) {
+                    return Err(RuntimeError::new(ErrorKind::Value, format!("int base must be between 2 and 36, got {}", radix)));
+                }
+                let digits = s.trim().replace('_', "");
+                match BigInt::parse(&digits, radix) {
+                    Some(value) => Ok(Value::from_bigint(value)),
+                    None => {
+                        let message = format!("invalid literal for int with base {}: {}", radix, self.__repr__());
+                        Err(RuntimeError::new(ErrorKind::Value, message))
+                    }
+                }
+            }
+            _ if radix != 10 => Err(RuntimeError::new(ErrorKind::Type, "int base is only allowed for strings")),
+            Value::Integer(_) | Value::BigInt(_) => Ok(self.clone()),
+            Value::Float(f) => match BigInt::from_f64(*f) {
+                Some(value) => Ok(Value::from_bigint(value)),
+                None => Err(RuntimeError::new(ErrorKind::Value, format!("cannot convert {} to int", self.__repr__()))),
+            },
+            value => Err(RuntimeError::new(ErrorKind::Type, format!("cannot convert '{}' to int", value.type_name()))),
+        }
+    }
+    /// Strings are parsed, `inf` and `nan` included
+    pub fn __float__(&self) -> Result<Self, RuntimeError> {
+        match self {
+            Value::String(s) => match s.trim().parse::<f64>() {
+                Ok(f) => Ok(Value::Float(f)),
+                Err(_) => Err(RuntimeError::new(ErrorKind::Value, format!("invalid literal for float: {}", self.__repr__()))),
+            },
+            value => match value.as_float() {
+                Some(f) => Ok(Value::Float(f)),
+                None => Err(RuntimeError::new(ErrorKind::Type, format!("cannot convert '{}' to float", value.type_name()))),
+            },
+        }
+    }
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) | Value::BigInt(_) => "int",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::None => "none",
+            Value::Tuple(_) => "tuple",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Code(_) => "code",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Module(_) => "module",
+            Value::StructType(_) => "struct type",
+            Value::Struct(_) => "struct",
+            Value::EnumType(_) => "enum type",
+            Value::Variant(_) => "enum",
+            Value::Iterator(_) => "iterator",
+            Value::File(_) => "file",
+        }
+    }
+    pub(crate) fn from_bigint(x: BigInt) -> Self {
+        match x.to_i64() {
+            Some(v) => Value::Integer(v),
+            None => Value::BigInt(Box::new(x)),
+        }
+    }
+    fn to_bigint(&self) -> BigInt {
+        match self {
+            Value::Integer(v) => BigInt::from(*v),
+            Value::BigInt(v) => (**v).clone(),
             _ => panic!("Expected integer"),
         }
     }
+    fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Integer(v) => Some(*v as f64),
+            Value::BigInt(v) => Some(v.to_f64()),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+    fn is_zero(&self) -> bool {
+        self.as_float() == Some(0.0)
+    }
+    fn operand_error(&self, op: &str, other: &Self) -> RuntimeError {
+        let message = format!("unsupported operand types for {}: '{}' and '{}'", op, self.type_name(), other.type_name());
+        RuntimeError::new(ErrorKind::Type, message)
+    }
+    /// Bit count of a shift, counts too large for `u64` saturate
+    fn shift_count(&self, other: &Self, op: &str) -> Result<u64, RuntimeError> {
+        let count = match (self, other) {
+            (Value::Integer(_) | Value::BigInt(_), Value::Integer(count)) => u64::try_from(*count).ok(),
+            (Value::Integer(_) | Value::BigInt(_), Value::BigInt(count)) if !count.is_negative() => Some(count.to_u64().unwrap_or(u64::MAX)),
+            (Value::Integer(_) | Value::BigInt(_), Value::BigInt(_)) => None,
+            _ => return Err(self.operand_error(op, other)),
+        };
+        count.ok_or_else(|| RuntimeError::new(ErrorKind::Value, "negative shift count"))
+    }
+    /// Bitwise operators take ints only, `BigInt`s act as two's complement
+    fn bitwise(&self, other: &Self, op: &str, int_op: fn(i64, i64) -> i64, limb_op: fn(u32, u32) -> u32) -> Result<Self, RuntimeError> {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(int_op(*a, *b))),
+            (Value::Integer(_) | Value::BigInt(_), Value::Integer(_) | Value::BigInt(_)) => {
+                Ok(Value::from_bigint(self.to_bigint().bitwise(&other.to_bigint(), limb_op)))
+            }
+            _ => Err(self.operand_error(op, other)),
+        }
+    }
+    /// Ints stay exact (promoting to `BigInt` on overflow), any float operand makes the result a float
+    fn arithmetic(
+        &self,
+        other: &Self,
+        op: &str,
+        int_op: fn(i64, i64) -> Option<i64>,
+        big_op: fn(&BigInt, &BigInt) -> BigInt,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Result<Self, RuntimeError> {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => match int_op(*a, *b) {
+                Some(x) => Ok(Value::Integer(x)),
+                None => Ok(Value::from_bigint(big_op(&self.to_bigint(), &other.to_bigint()))),
+            },
+            (Value::Integer(_) | Value::BigInt(_), Value::Integer(_) | Value::BigInt(_)) => {
+                Ok(Value::from_bigint(big_op(&self.to_bigint(), &other.to_bigint())))
+            }
+            _ => match (self.as_float(), other.as_float()) {
+                (Some(a), Some(b)) => Ok(Value::Float(float_op(a, b))),
+                _ => Err(self.operand_error(op, other)),
+            },
+        }
+    }
+    /// Ordering of numbers and of strings, `None` when a NaN is involved
+    fn compare(&self, other: &Self, op: &str) -> Result<Option<Ordering>, RuntimeError> {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => Ok(Some(a.cmp(b))),
+            (Value::Integer(_) | Value::BigInt(_), Value::Integer(_) | Value::BigInt(_)) => {
+                Ok(Some(self.to_bigint().cmp(&other.to_bigint())))
+            }
+            (Value::String(a), Value::String(b)) => Ok(Some(a.cmp(b))),
+            _ => match (self.as_float(), other.as_float()) {
+                (Some(a), Some(b)) => Ok(a.partial_cmp(&b)),
+                _ => Err(self.operand_error(op, other)),
+            },
+        }
+    }
+    /// Values of unrelated types are never equal
+    fn equals(&self, other: &Self) -> bool {
+        let all_equal = |a: &[Value], b: &[Value]| a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.equals(y));
+        match (self, other) {
+            (Value::None, Value::None) => true,
+            (Value::Tuple(a), Value::Tuple(b)) => all_equal(a, b),
+            (Value::List(a), Value::List(b)) => all_equal(&a.borrow(), &b.borrow()),
+            (Value::Map(a), Value::Map(b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|((ka, va), (kb, vb))| ka == kb && va.equals(vb))
+            }
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Module(a), Value::Module(b)) => Rc::ptr_eq(a, b),
+            (Value::StructType(a), Value::StructType(b)) => Rc::ptr_eq(a, b),
+            (Value::EnumType(a), Value::EnumType(b)) => Rc::ptr_eq(a, b),
+            (Value::File(a), Value::File(b)) => Rc::ptr_eq(a, b),
+            (Value::Struct(a), Value::Struct(b)) => {
+                Rc::ptr_eq(&a.ty, &b.ty) && all_equal(&a.fields.borrow(), &b.fields.borrow())
+            }
+            (Value::Variant(a), Value::Variant(b)) => {
+                Rc::ptr_eq(&a.ty, &b.ty) && a.index == b.index && all_equal(&a.values, &b.values)
+            }
+            _ => matches!(self.compare(other, "=="), Ok(Some(Ordering::Equal))),
+        }
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn map_key(key: &Value) -> Result<&String, RuntimeError> {
+    match key {
+        Value::String(key) => Ok(key),
+        key => Err(RuntimeError::new(ErrorKind::Type, format!("map keys must be strings, not '{}'", key.type_name()))),
+    }
+}
+
+fn join_repr<'a>(items: impl Iterator<Item = &'a Value>) -> String {
+    items.map(Value::__repr__).collect::<Vec<_>>().join(", ")
+}
+
+/// Floored `i64` division, `None` on overflow or zero divisor
+fn floor_div_rem(a: i64, b: i64) -> Option<(i64, i64)> {
+    let (q, r) = (a.checked_div(b)?, a.checked_rem(b)?);
+    if r != 0 && (r < 0) != (b < 0) {
+        Some((q - 1, r + b))
+    } else {
+        Some((q, r))
+    }
+}
+
+/// Largest width or precision of a format spec, padding is allocated up front.
+/// It is also the largest precision `format!` accepts
+const MAX_FORMAT_WIDTH: usize = u16::MAX as usize;
+
+struct FormatSpec {
+    fill: char,
+    align: Option<char>,
+    sign: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Result<Self, RuntimeError> {
+        let is_align = |c: char| matches!(c, '<' | '>' | '^');
+        let mut chars: Vec<char> = spec.chars().collect();
+
+        let (fill, align) = match chars.as_slice() {
+            [fill, align, ..] if is_align(*align) => {
+                let res = (*fill, Some(*align));
+                chars.drain(..2);
+                res
+            }
+            [align, ..] if is_align(*align) => {
+                let res = (' ', Some(*align));
+                chars.drain(..1);
+                res
+            }
+            _ => (' ', None),
+        };
+        let rest: String = chars.into_iter().collect();
+
+        let rest_unsigned = rest.strip_prefix('+');
+        let sign = rest_unsigned.is_some();
+        let rest = rest_unsigned.unwrap_or(&rest);
+
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(parse_size(precision, "precision")?)),
+            None => (rest, None),
+        };
+        let zero = width.starts_with('0');
+
+        Ok(FormatSpec {
+            fill,
+            align,
+            sign,
+            zero,
+            width: parse_size(width, "width")?,
+            precision,
+        })
+    }
+}
+
+/// Digits of a width or precision, which only fail to parse when empty or too large
+fn parse_size(digits: &str, what: &str) -> Result<usize, RuntimeError> {
+    match digits.parse() {
+        Ok(size) if size <= MAX_FORMAT_WIDTH => Ok(size),
+        _ if digits.is_empty() => Ok(0),
+        _ => Err(RuntimeError::new(ErrorKind::Value, format!("format {} {} is larger than {}", what, digits, MAX_FORMAT_WIDTH))),
+    }
 }
 
 
@@ -85,4 +970,133 @@ mod tests {
     fn value_size() {
         assert_eq!(std::mem::size_of::<Value>() * 8, 128);
     }
+    #[test]
+    fn integer_overflow_promotes() {
+        let max = Value::Integer(i64::MAX);
+        let big = max.clone().__add__(Value::Integer(1)).unwrap();
+        assert!(matches!(big, Value::BigInt(_)));
+        assert_eq!(big.__repr__(), "9223372036854775808");
+        assert!(big.clone().__gt__(max.clone()).unwrap().as_bool());
+        assert!(matches!(big.__sub__(Value::Integer(1)).unwrap(), Value::Integer(i64::MAX)));
+        let min_div = Value::Integer(i64::MIN).__floordiv__(Value::Integer(-1)).unwrap();
+        assert_eq!(min_div.__repr__(), "9223372036854775808");
+    }
+    #[test]
+    fn division() {
+        let int = |x| Value::Integer(x);
+        assert_eq!(int(7).__div__(int(2)).unwrap().__repr__(), "3.5");
+        assert_eq!(int(-7).__floordiv__(int(2)).unwrap().__repr__(), "-4");
+        assert_eq!(int(-7).__mod__(int(2)).unwrap().__repr__(), "1");
+        assert_eq!(int(7).__mod__(int(-2)).unwrap().__repr__(), "-1");
+        assert_eq!(Value::Float(-7.5).__mod__(int(2)).unwrap().__repr__(), "0.5");
+        assert_eq!(Value::Float(7.5).__floordiv__(int(-2)).unwrap().__repr__(), "-4.0");
+        for op in [Value::__div__, Value::__floordiv__, Value::__mod__] {
+            let err = op(int(1), int(0)).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::ZeroDivision);
+        }
+    }
+    #[test]
+    fn power() {
+        let int = |x| Value::Integer(x);
+        assert_eq!(int(2).__pow__(int(10)).unwrap().__repr__(), "1024");
+        assert_eq!(int(-3).__pow__(int(41)).unwrap().__repr__(), "-36472996377170786403");
+        assert_eq!(int(2).__pow__(int(-2)).unwrap().__repr__(), "0.25");
+        assert_eq!(Value::Float(2.25).__pow__(Value::Float(0.5)).unwrap().__repr__(), "1.5");
+        assert_eq!(int(0).__pow__(int(-1)).unwrap_err().kind, ErrorKind::ZeroDivision);
+        let huge = int(i64::MAX).__add__(int(1)).unwrap();
+        assert_eq!(int(2).__pow__(huge).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(int(2).__pow__(Value::None).unwrap_err().kind, ErrorKind::Type);
+    }
+    #[test]
+    fn shifts() {
+        let int = |x| Value::Integer(x);
+        assert_eq!(int(3).__lshift__(int(4)).unwrap().__repr__(), "48");
+        assert_eq!(int(1).__lshift__(int(64)).unwrap().__repr__(), "18446744073709551616");
+        assert_eq!(int(-1).__lshift__(int(63)).unwrap().__repr__(), "-9223372036854775808");
+        assert_eq!(int(-7).__rshift__(int(1)).unwrap().__repr__(), "-4");
+        assert_eq!(int(-7).__rshift__(int(100)).unwrap().__repr__(), "-1");
+        let big = int(1).__lshift__(int(100)).unwrap();
+        assert_eq!(big.__rshift__(int(98)).unwrap().__repr__(), "4");
+        assert_eq!(int(0).__lshift__(int(1 << 40)).unwrap().__repr__(), "0");
+        assert_eq!(int(1).__lshift__(int(1 << 40)).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(int(1).__rshift__(int(-1)).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(Value::Float(1.0).__lshift__(int(1)).unwrap_err().kind, ErrorKind::Type);
+    }
+    #[test]
+    fn bitwise() {
+        let int = |x| Value::Integer(x);
+        assert_eq!(int(12).__and__(int(10)).unwrap().__repr__(), "8");
+        assert_eq!(int(12).__or__(int(10)).unwrap().__repr__(), "14");
+        assert_eq!(int(12).__xor__(int(10)).unwrap().__repr__(), "6");
+        assert_eq!(int(5).__invert__().unwrap().__repr__(), "-6");
+        let big = int(1).__lshift__(int(70)).unwrap();
+        assert_eq!(big.clone().__or__(int(1)).unwrap().__repr__(), "1180591620717411303425");
+        assert_eq!(big.clone().__and__(int(-1)).unwrap().__repr__(), big.__repr__());
+        assert_eq!(big.clone().__xor__(big.clone()).unwrap().__repr__(), "0");
+        assert_eq!(big.__invert__().unwrap().__repr__(), "-1180591620717411303425");
+        assert_eq!(int(1).__and__(Value::Float(1.0)).unwrap_err().kind, ErrorKind::Type);
+    }
+    #[test]
+    fn format_spec() {
+        let name = Value::String(Box::new("joe".to_string()));
+        assert_eq!(name.__format__("").unwrap(), "joe");
+        assert_eq!(name.__format__(">5").unwrap(), "  joe");
+        assert_eq!(name.__format__("*^7").unwrap(), "**joe**");
+        assert_eq!(name.__format__(".2").unwrap(), "jo");
+        assert_eq!(Value::Integer(42).__format__("5").unwrap(), "   42");
+        assert_eq!(Value::Integer(42).__format__("<5").unwrap(), "42   ");
+        assert_eq!(Value::Integer(-42).__format__("06").unwrap(), "-00042");
+        assert_eq!(Value::Integer(42).__format__("+").unwrap(), "+42");
+        assert_eq!(Value::Float(1.23456).__format__("8.3").unwrap(), "   1.235");
+        assert_eq!(name.__format__("65535").unwrap().len(), MAX_FORMAT_WIDTH);
+        for spec in ["65536", "99999999999999999999", ".99999999999999999999"] {
+            assert_eq!(Value::Integer(1).__format__(spec).unwrap_err().kind, ErrorKind::Value);
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        let s = |s: &str| Value::String(Box::new(s.to_string()));
+        assert_eq!(s(" -12 ").__int__(10).unwrap().__repr__(), "-12");
+        assert_eq!(s("z").__int__(36).unwrap().__repr__(), "35");
+        assert_eq!(s("abc").__int__(10).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(Value::Float(f64::INFINITY).__int__(10).unwrap_err().kind, ErrorKind::Value);
+        assert_eq!(Value::None.__int__(10).unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(s("1.5").__float__().unwrap().__repr__(), "1.5");
+        assert_eq!(s("héllo").__len__().unwrap(), 5);
+        assert_eq!(Value::Integer(1).__len__().unwrap_err().kind, ErrorKind::Type);
+        assert!(!s("").as_bool() && !Value::None.as_bool() && Value::Integer(-1).as_bool());
+    }
+
+    #[test]
+    fn str_and_repr() {
+        let s = |s: &str| Value::String(Box::new(s.to_string()));
+        assert_eq!(s("a\"b").__str__(), "a\"b");
+        assert_eq!(s("a\"b").__repr__(), "\"a\\\"b\"");
+        let list = Value::new_list(vec![s("x"), Value::Tuple(Rc::new(vec![s("y")]))]);
+        assert_eq!(list.__str__(), "[\"x\", (\"y\",)]");
+
+        assert_eq!(Value::Float(f64::NAN).__str__(), "nan");
+        assert_eq!(Value::Float(-f64::NAN).__repr__(), "nan");
+        assert_eq!(Value::Float(f64::INFINITY).__str__(), "inf");
+        assert_eq!(Value::Float(f64::NEG_INFINITY).__repr__(), "-inf");
+    }
+
+    #[test]
+    fn struct_field_visibility() {
+        let fields = vec![
+            FieldDef { name: "x".to_string(), public: true },
+            FieldDef { name: "secret".to_string(), public: false },
+        ];
+        let ty = Rc::new(StructType { name: "Point".to_string(), module: "geo".to_string(), fields });
+        let names = [Value::String(Box::new("secret".to_string())), Value::String(Box::new("x".to_string()))];
+        let point = StructType::instantiate(&ty, &names, vec![Value::Integer(2), Value::Integer(1)], "geo").unwrap();
+
+        assert_eq!(point.__repr__(), "Point { x: 1, secret: 2 }");
+        assert!(point.get_attr("x", "main").is_ok());
+        assert!(point.get_attr("secret", "geo").is_ok());
+        assert!(point.get_attr("secret", "main").is_err());
+        assert!(StructType::instantiate(&ty, &names, vec![Value::Integer(2), Value::Integer(1)], "main").is_err());
+        assert!(StructType::instantiate(&ty, &names[1..], vec![Value::Integer(1)], "geo").is_err());
+    }
 }
@@ -1,43 +1,249 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Read};
+use std::rc::Rc;
+
+use crate::builtins::{self, Clock, Rng};
 use crate::code_obj::CodeObject;
-use crate::value::Value;
+use crate::convert::{type_error, IntoValue};
+use crate::error::{ErrorKind, RuntimeError};
+use crate::native::Natives;
+use crate::value::{Function, Module, StructType, Value};
 use crate::opcode::OpCode;
 use crate::opcode::OpCode::*;
 
-pub struct VM {
+/// Deepest nesting of calls, deeper recursion raises a `RecursionError`
+/// instead of growing the frames until memory runs out
+const MAX_CALL_DEPTH: usize = 10_000;
+
+struct Frame {
+    co: Rc<CodeObject>,
+    /// Module whose globals are visible to the code
+    module: Rc<Module>,
     pc: usize,
+    /// Stack position of the first local, the callee sits right below it
+    base: usize,
+    /// Body of a module being imported, the module is only kept if it completes
+    importing: bool,
+}
+
+impl Frame {
+    fn read_arg(&mut self) -> usize {
+        let arg = self.co.read_operand(self.pc);
+        self.pc += 2;
+        arg as usize
+    }
+}
+
+pub struct VM {
+    frame: Frame,
+    /// Callers of the current frame
+    frames: Vec<Frame>,
     stack: Vec<Value>,
-    vars: Vec<Value>,
+    /// Imported modules by name, each module body runs once
+    modules: HashMap<String, Rc<Module>>,
+    pub(crate) natives: Natives,
+    /// Command line arguments of the script, also visible as `sys.args`
+    args: Vec<String>,
+    /// Source of `input` and `read_line`, process stdin when not set
+    input: Option<Box<dyn BufRead>>,
+    /// Generator of the `random` module, seeded from the clock unless set
+    pub(crate) rng: Rng,
+    pub(crate) clock: Clock,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
 }
 
 impl VM {
     pub fn new() -> Self {
+        let co = Rc::new(CodeObject::new_empty());
+        let module = Rc::new(Module::new(&co));
+        let mut natives = Natives::new();
+        builtins::register(&mut natives);
         VM {
-            pc: 0,
+            frame: Frame { co, module, pc: 0, base: 0, importing: false },
+            frames: vec![],
             stack: vec![],
-            vars: vec![],
+            modules: HashMap::new(),
+            natives,
+            args: vec![],
+            input: None,
+            rng: Rng::from_time(),
+            clock: Clock::system(),
+        }
+    }
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+    pub fn set_args(&mut self, args: Vec<String>) {
+        if let Some(sys) = self.natives.module("sys") {
+            sys.set("args", args.clone().into_value());
         }
+        self.args = args;
+    }
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = Some(input);
+    }
+    /// Makes the `time` module read a virtual clock starting at `epoch` seconds,
+    /// `time.sleep` advances it instead of waiting
+    pub fn set_virtual_clock(&mut self, epoch: f64) {
+        self.clock = Clock::Virtual { epoch, elapsed: 0.0 };
+    }
+    /// Restarts the generator of the `random` module, as `random.seed` does
+    pub fn seed_random(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+    /// Reads a line including its terminator, empty at end of input
+    pub fn read_line(&mut self) -> Result<String, RuntimeError> {
+        let mut line = String::new();
+        match &mut self.input {
+            Some(input) => input.read_line(&mut line),
+            None => std::io::stdin().read_line(&mut line),
+        }.map_err(RuntimeError::io)?;
+        Ok(line)
+    }
+    pub fn read_all(&mut self) -> Result<String, RuntimeError> {
+        let mut text = String::new();
+        match &mut self.input {
+            Some(input) => input.read_to_string(&mut text),
+            None => std::io::stdin().read_to_string(&mut text),
+        }.map_err(RuntimeError::io)?;
+        Ok(text)
     }
     pub fn print_state(&self) {
-        println!("PC: {}", self.pc);
+        println!("PC: {}", self.frame.pc);
         println!("stack: {:?}", self.stack);
-        println!("vars: {:?}", self.vars);
+        println!("vars: {:?}", self.frame.module.globals.borrow());
+    }
+    fn call(&mut self, argc: usize) -> Result<(), RuntimeError> {
+        let base = self.stack.len() - argc;
+        let function = match &self.stack[base - 1] {
+            Value::Function(function) => function.clone(),
+            Value::Native(native) => {
+                let native = native.clone();
+                native.signature.check(&native.name, argc).map_err(|message| RuntimeError::new(ErrorKind::Type, message))?;
+                // Dynamic calls cannot name keyword arguments, they are all left out
+                let mut args = self.stack.split_off(base);
+                let padded = argc + native.signature.missing(argc) + native.signature.keywords.len();
+                args.resize(padded, Value::None);
+                let result = (native.function)(self, &args)?;
+                *self.stack.last_mut().unwrap() = result;
+                return Ok(());
+            }
+            value => {
+                let message = format!("'{}' is not callable", value.type_name());
+                return Err(RuntimeError::new(ErrorKind::Type, message));
+            }
+        };
+        let co = function.co.clone();
+        if co.arity != argc {
+            let message = format!("{} takes {} arguments but {} were given", co.name, co.arity, argc);
+            return Err(RuntimeError::new(ErrorKind::Type, message));
+        }
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            let message = format!("maximum call depth of {} exceeded in {}", MAX_CALL_DEPTH, co.name);
+            return Err(RuntimeError::new(ErrorKind::Recursion, message));
+        }
+
+        self.stack.resize(base + co.nlocals, Value::None);
+        let callee = Frame { co, module: function.module.clone(), pc: 0, base, importing: false };
+        self.frames.push(std::mem::replace(&mut self.frame, callee));
+        Ok(())
     }
-    pub fn run(&mut self, co: CodeObject) {        
+    /// Pushes the module if it already ran, otherwise starts running its body,
+    /// `halt` at the end of the body pushes the module
+    fn import_module(&mut self, co: Rc<CodeObject>) {
+        if let Some(module) = self.modules.get(&co.name) {
+            self.stack.push(Value::Module(module.clone()));
+            return;
+        }
+        let module = Rc::new(Module::new(&co));
+        self.modules.insert(co.name.clone(), module.clone());
+        let body = Frame { co, module, pc: 0, base: self.stack.len(), importing: true };
+        self.frames.push(std::mem::replace(&mut self.frame, body));
+    }
+    /// Calls `function` from outside of bytecode through a small stub which
+    /// pushes it with its arguments, calls it and halts with the result,
+    /// natives cannot use it as it runs through `run_in`
+    pub(crate) fn call_function(&mut self, function: Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let module = match &function {
+            Value::Function(function) => function.module.clone(),
+            _ => self.frame.module.clone(),
+        };
+        let argc = args.len() as u16;
+        let mut co = CodeObject::new_function("<call>", 0);
+        for (index, value) in std::iter::once(function).chain(args).enumerate() {
+            co.consts.push(value);
+            co.code.push(push_const as u8);
+            co.code.extend((index as u16).to_le_bytes());
+        }
+        co.code.push(call as u8);
+        co.code.extend(argc.to_le_bytes());
+        co.code.push(halt as u8);
+        self.run_in(Rc::new(co), module)
+    }
+    /// Runs `co` with the globals of an existing module, returns the value
+    /// left on the stack by `halt`, which is none for module bodies. Frames and
+    /// the stack start over, so it must not run while other code is running
+    pub(crate) fn run_in(&mut self, co: Rc<CodeObject>, module: Rc<Module>) -> Result<Value, RuntimeError> {
+        module.extend(&co);
+        self.frames.clear();
+        self.stack.clear();
+        self.frame = Frame { co, module, pc: 0, base: 0, importing: false };
+
         loop {
-            let opcode = OpCode::from_u8(co.code[self.pc]);
+            match self.dispatch() {
+                Ok(value) => return Ok(value),
+                Err(error) => self.unwind(error)?,
+            }
+        }
+    }
+    /// Moves execution to the innermost handler covering the failed instruction,
+    /// leaving frames without one. The error is returned when nothing handles it,
+    /// modules whose body is left are forgotten so a later import runs them again
+    fn unwind(&mut self, error: RuntimeError) -> Result<(), RuntimeError> {
+        loop {
+            let handler = match error.kind.is_catchable() {
+                true => self.frame.co.find_handler(self.frame.pc).copied(),
+                false => None,
+            };
+            if let Some(handler) = handler {
+                self.stack.truncate(self.frame.base + self.frame.co.nlocals + handler.depth as usize);
+                self.stack.push(builtins::Error::caught(error));
+                self.frame.pc = handler.target as usize;
+                return Ok(());
+            }
+            if self.frame.importing {
+                self.modules.remove(&self.frame.co.name);
+            }
+            self.frame = match self.frames.pop() {
+                Some(caller) => caller,
+                None => return Err(error),
+            };
+        }
+    }
+    /// Executes instructions until the outermost frame halts or an error is raised
+    fn dispatch(&mut self) -> Result<Value, RuntimeError> {
+        loop {
+            let opcode = OpCode::from_u8(self.frame.co.code[self.frame.pc]);
             // println!("OPCODE PC {}", pc);
-            self.pc += 1;
+            self.frame.pc += 1;
 
             match opcode {
                 push_const => {
-                    let const_index = co.code[self.pc] as usize;
-                    self.pc += 1;
-                    self.stack.push(co.consts[const_index].clone());
+                    let const_index = self.frame.read_arg();
+                    self.stack.push(self.frame.co.consts[const_index].clone());
                 }
                 push_var => {
-                    let var_index = co.code[self.pc] as usize;
-                    self.pc += 1;
-                    self.stack.push(self.vars[var_index].clone());
+                    let var_index = self.frame.read_arg();
+                    self.stack.push(self.frame.module.globals.borrow()[var_index].clone());
+                }
+                push_local => {
+                    let local_index = self.frame.read_arg();
+                    self.stack.push(self.stack[self.frame.base + local_index].clone());
                 }
                 pop => {
                     self.stack.pop().unwrap();
@@ -45,95 +251,298 @@ impl VM {
                 copy => {
                     self.stack.push(self.stack[self.stack.len() - 1].clone());
                 }
+                copy2 => {
+                    self.stack.extend_from_within(self.stack.len() - 2..);
+                }
                 store_var => {
-                    let var_index = co.code[self.pc] as usize;
-                    self.pc += 1;
+                    let var_index = self.frame.read_arg();
                     let topval = self.stack.pop().unwrap();
-                    if var_index == self.vars.len() {
-                        self.vars.push(topval);
-                    } else if var_index < self.vars.len() {
-                        self.vars[var_index] = topval;
-                    } else {
-                        panic!("Bad bytecode")
+                    self.frame.module.globals.borrow_mut()[var_index] = topval;
+                }
+                store_local => {
+                    let local_index = self.frame.read_arg();
+                    let topval = self.stack.pop().unwrap();
+                    self.stack[self.frame.base + local_index] = topval;
+                }
+                format_value => {
+                    let spec_index = self.frame.read_arg();
+                    let spec = self.frame.co.consts[spec_index].__str__();
+                    let value = self.stack.pop().unwrap();
+                    self.stack.push(Value::String(Box::new(value.__format__(&spec)?)));
+                }
+                build_string => {
+                    let count = self.frame.read_arg();
+                    let parts = self.stack.split_off(self.stack.len() - count);
+                    let s: String = parts.iter().map(Value::__str__).collect();
+                    self.stack.push(Value::String(Box::new(s)));
+                }
+                build_tuple => {
+                    let count = self.frame.read_arg();
+                    let items = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::Tuple(Rc::new(items)));
+                }
+                build_list => {
+                    let count = self.frame.read_arg();
+                    let items = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::new_list(items));
+                }
+                build_map => {
+                    let count = self.frame.read_arg();
+                    let mut items = self.stack.split_off(self.stack.len() - 2 * count).into_iter();
+                    let entries = std::iter::from_fn(|| Some((items.next()?, items.next()?)));
+                    self.stack.push(Value::new_map(entries.collect())?);
+                }
+                build_struct => {
+                    let names_index = self.frame.read_arg();
+                    let names = match &self.frame.co.consts[names_index] {
+                        Value::Tuple(names) => names.clone(),
+                        _ => panic!("Bad bytecode"),
+                    };
+                    let values = self.stack.split_off(self.stack.len() - names.len());
+                    let instance = match self.stack.pop().unwrap() {
+                        Value::StructType(ty) => StructType::instantiate(&ty, &names, values, &self.frame.module.name)?,
+                        value => {
+                            let message = format!("'{}' is not a struct", value.type_name());
+                            return Err(RuntimeError::new(ErrorKind::Type, message));
+                        }
+                    };
+                    self.stack.push(instance);
+                }
+                unpack => {
+                    let count = self.frame.read_arg();
+                    let items = self.stack.pop().unwrap().unpack(count)?;
+                    self.stack.extend(items.into_iter().rev());
+                }
+                get_item => {
+                    let index = self.stack.pop().unwrap();
+                    let container = self.stack.pop().unwrap();
+                    self.stack.push(container.__getitem__(&index)?);
+                }
+                set_item => {
+                    let value = self.stack.pop().unwrap();
+                    let index = self.stack.pop().unwrap();
+                    let container = self.stack.pop().unwrap();
+                    container.__setitem__(&index, value)?;
+                }
+                get_iter => {
+                    let iterable = self.stack.pop().unwrap();
+                    self.stack.push(iterable.__iter__()?);
+                }
+                for_iter => {
+                    let jump_position = self.frame.read_arg();
+                    let next = match self.stack.last_mut() {
+                        Some(Value::Iterator(iter)) => iter.next(),
+                        Some(Value::File(file)) => file.read_line()?.map(IntoValue::into_value),
+                        _ => panic!("Bad bytecode"),
+                    };
+                    match next {
+                        Some(item) => self.stack.push(item),
+                        None => {
+                            self.stack.pop();
+                            self.frame.pc = jump_position;
+                        }
                     }
                 }
-                print => {
-                    println!("{}", self.stack.pop().unwrap().__repr__());
-                },
-                jmp => {
-                    let jmp_pos = co.code[self.pc] as usize;
-                    self.pc = jmp_pos;
-                }
-                jmp_nonzero => {
-                    let top = self.stack[self.stack.len() - 1].clone();
-                    if top.__ne__(Value::Integer(0)).as_bool() {
-                        let jump_position = co.code[self.pc];
-                        self.pc = jump_position as usize;
-                    } else {
-                        self.pc += 1;
+                make_function => {
+                    let co = match self.stack.pop().unwrap() {
+                        Value::Code(co) => co,
+                        _ => panic!("Bad bytecode"),
+                    };
+                    let module = self.frame.module.clone();
+                    self.stack.push(Value::Function(Rc::new(Function { co, module })));
+                }
+                call => {
+                    let argc = self.frame.read_arg();
+                    self.call(argc)?;
+                }
+                call_method => {
+                    let name_index = self.frame.read_arg();
+                    let argc = self.frame.read_arg();
+                    let name = self.frame.co.consts[name_index].__str__();
+                    let receiver_pos = self.stack.len() - argc - 1;
+
+                    // Module members are plain calls, the member replaces the module on the stack
+                    if let Value::Module(module) = &self.stack[receiver_pos] {
+                        self.stack[receiver_pos] = Value::Module(module.clone()).get_attr(&name, &self.frame.module.name)?;
+                        self.call(argc)?;
+                        continue;
                     }
+
+                    let args = self.stack.split_off(receiver_pos + 1);
+                    let receiver = self.stack.pop().unwrap();
+                    self.stack.push(receiver.call_method(&name, args)?);
+                }
+                call_native => {
+                    let native_index = self.frame.read_arg();
+                    let argc = self.frame.read_arg();
+                    let function = self.natives.get(native_index).function;
+                    let args = self.stack.split_off(self.stack.len() - argc);
+                    let result = function(self, &args)?;
+                    self.stack.push(result);
+                }
+                import_module => {
+                    let module_index = self.frame.read_arg();
+                    let co = match &self.frame.co.consts[module_index] {
+                        Value::Code(co) => co.clone(),
+                        _ => panic!("Bad bytecode"),
+                    };
+                    self.import_module(co);
+                }
+                get_attr => {
+                    let name_index = self.frame.read_arg();
+                    let name = self.frame.co.consts[name_index].__str__();
+                    let value = self.stack.pop().unwrap();
+                    self.stack.push(value.get_attr(&name, &self.frame.module.name)?);
+                }
+                set_attr => {
+                    let name_index = self.frame.read_arg();
+                    let name = self.frame.co.consts[name_index].__str__();
+                    let value = self.stack.pop().unwrap();
+                    let target = self.stack.pop().unwrap();
+                    target.set_attr(&name, value, &self.frame.module.name)?;
+                }
+                ret => {
+                    let value = self.stack.pop().unwrap();
+                    self.stack.truncate(self.frame.base - 1);
+                    self.stack.push(value);
+                    self.frame = self.frames.pop().expect("Return from module code");
+                }
+                jmp => {
+                    let jmp_pos = self.frame.read_arg();
+                    self.frame.pc = jmp_pos;
                 }
                 pop_jmp_ifzero => {
                     let top = self.stack.pop().unwrap();
-                    if top.__et__(Value::Integer(0)).as_bool() {
-                        let jump_position = co.code[self.pc];
-                        self.pc = jump_position as usize;
+                    let jump_position = self.frame.read_arg();
+                    if !top.as_bool() {
+                        self.frame.pc = jump_position;
+                    }
+                }
+                unwrap_ok => {
+                    let jump_position = self.frame.read_arg();
+                    let depth = self.frame.read_arg();
+                    let result = match self.stack.pop().unwrap() {
+                        Value::Variant(variant) if variant.is_result() => variant,
+                        value => return Err(type_error("Result", &value)),
+                    };
+                    if result.is_ok() {
+                        self.stack.push(result.values[0].clone());
+                        self.frame.pc = jump_position;
                     } else {
-                        self.pc += 1;
+                        self.stack.truncate(self.frame.base + self.frame.co.nlocals + depth);
+                        self.stack.push(Value::Variant(result));
                     }
                 }
                 halt => {
-                    break;
+                    match self.frames.pop() {
+                        Some(importer) => {
+                            let module = std::mem::replace(&mut self.frame, importer).module;
+                            self.stack.push(Value::Module(module));
+                        }
+                        None => return Ok(self.stack.pop().unwrap_or(Value::None)),
+                    }
                 },
+                throw => {
+                    let value = self.stack.pop().unwrap();
+                    return Err(builtins::Error::thrown(value));
+                }
                 add => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__add__(b));
+                    self.stack.push(a.__add__(b)?);
                 },
                 sub => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__sub__(b));
+                    self.stack.push(a.__sub__(b)?);
                 },
                 mul => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__mul__(b));
+                    self.stack.push(a.__mul__(b)?);
                 },
                 div => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__div__(b));
+                    self.stack.push(a.__div__(b)?);
+                },
+                floor_div => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__floordiv__(b)?);
+                },
+                rem => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__mod__(b)?);
+                },
+                pow => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__pow__(b)?);
+                },
+                neg => {
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__neg__()?);
+                },
+                lshift => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__lshift__(b)?);
+                },
+                rshift => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__rshift__(b)?);
+                },
+                bit_and => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__and__(b)?);
+                },
+                bit_or => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__or__(b)?);
+                },
+                bit_xor => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__xor__(b)?);
+                },
+                bit_not => {
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(a.__invert__()?);
                 },
                 gt => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__gt__(b));
+                    self.stack.push(a.__gt__(b)?);
                 },
                 lt => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__lt__(b));
+                    self.stack.push(a.__lt__(b)?);
                 },
                 et => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__et__(b));
+                    self.stack.push(a.__et__(b)?);
                 },
                 ne => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__ne__(b));
+                    self.stack.push(a.__ne__(b)?);
                 },
                 ge => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__ge__(b));
+                    self.stack.push(a.__ge__(b)?);
                 },
                 le => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a.__le__(b));
+                    self.stack.push(a.__le__(b)?);
                 },
             }
         }